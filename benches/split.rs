@@ -0,0 +1,74 @@
+//! Split throughput, plus the number of allocations a single split makes, printed before the
+//! measurements, and the subdivision of a single large element:
+//!
+//! ```shell
+//! cargo bench --bench split
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use dumb_html_splitter::options::SplitOptions;
+use dumb_html_splitter::token_group::TokenGroup;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn split(c: &mut Criterion) {
+    let html = include_str!("../src/test_data/sample1.html").repeat(20);
+    let cases = [
+        ("plain", SplitOptions::default().max_chunk_size(1000)),
+        (
+            "overlap",
+            SplitOptions::default().max_chunk_size(1000).overlap(200),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("split");
+    group.throughput(Throughput::Bytes(html.len() as u64));
+    for (name, options) in cases {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let chunks = options.split(&html).map_or(0, |chunks| chunks.len());
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("split/{name}: {allocations} allocations for {chunks} chunks");
+
+        group.bench_function(name, |b| b.iter(|| options.split(black_box(&html))));
+    }
+    group.finish();
+}
+
+fn subdivide(c: &mut Criterion) {
+    let item = "<li><b>bold <i>italic</i></b> <a href='x'>link</a> text</li>";
+    let html = format!("<ul>{}</ul>", item.repeat(2000));
+    let tg = TokenGroup::from_string(&html);
+
+    let mut group = c.benchmark_group("subdivide");
+    group.throughput(Throughput::Bytes(html.len() as u64));
+    group.bench_function("list", |b| {
+        b.iter(|| black_box(&tg).subdivide(200, &["a", "b"]))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, split, subdivide);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Compare the tokenizer throughput with and without SIMD scanning:
+//!
+//! ```shell
+//! cargo bench --bench tokenizer
+//! cargo bench --bench tokenizer --features memchr
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use dumb_html_splitter::tokenizer::Tokenizer;
+
+fn tokenize(c: &mut Criterion) {
+    let html = include_str!("../src/test_data/sample1.html").repeat(200);
+
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(html.len() as u64));
+    group.bench_function("sample1", |b| {
+        b.iter(|| Tokenizer::new(black_box(&html)).count())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tokenize);
+criterion_main!(benches);
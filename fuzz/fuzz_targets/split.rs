@@ -0,0 +1,12 @@
+//! Checks that `split` never panics, and that the chunks it produces keep all of the text.
+//!
+//! Run with `cargo +nightly fuzz run split` from the repository root. Add the inputs that used
+//! to fail to `test_split_regressions` in `src/lib.rs`, which runs without the `arbitrary` feature.
+#![no_main]
+
+use dumb_html_splitter::fuzz::{check_split, SplitInput};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: SplitInput| {
+    check_split(&input.html.0, input.max_chunk_size);
+});
@@ -4,19 +4,258 @@ use std::fmt::Formatter;
 
 #[derive(Debug)]
 pub enum SplitError<'a> {
-    SubdivisionImpossible(TokenGroup<'a>),
+    /// A group that can't be subdivided within the limit, along with the smallest limit it can.
+    /// `open_tags` are the names of the tags open where it failed, outermost first; they're
+    /// reopened in the group and closed at its end, taking up the room.
+    SubdivisionImpossible {
+        group: TokenGroup<'a>,
+        open_tags: Vec<&'a str>,
+        min_chunk_size: usize,
+    },
     SubdivisionImpossibleUnicode(Token<'a>),
     SubdividedExceedingTheLimit(Vec<TokenGroup<'a>>),
     SplitExceededTheLimit(Vec<String>),
+    /// Same as [`SplitError::SplitExceededTheLimit`], but the chunks went to the writer of
+    /// [`crate::split_to_writer`]; the number of chunks written
+    WrittenExceedingTheLimit(usize),
     UnbalancedToken(Token<'a>),
     InvalidLen(usize),
+    /// An option of [`crate::options::SplitOptions`] set to a value it can't split with, like
+    /// `max_tags_per_chunk` of 0
+    InvalidOption {
+        option: &'static str,
+        value: usize,
+    },
+    /// The tags are nested deeper than [`crate::options::SplitOptions::max_depth`]
+    MaxDepthExceeded(usize),
+    /// An unterminated tag or comment, or a stray `<`, in [`crate::options::SplitOptions::strict`]
+    /// mode; `snippet` is the beginning of it
+    MalformedTag {
+        at: usize,
+        snippet: &'a str,
+    },
+    /// A chunk is not balanced, see [`crate::options::SplitOptions::verify`]
+    VerificationFailed {
+        chunk_index: usize,
+    },
+    /// Writing the chunks failed, see [`crate::split_to_writer`]
+    Io(std::io::Error),
+}
+
+/// Same as [`SplitError`], but owns the text of the tokens, so it outlives the source document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnedSplitError {
+    SubdivisionImpossible {
+        group: String,
+        open_tags: Vec<String>,
+        min_chunk_size: usize,
+    },
+    SubdivisionImpossibleUnicode(String),
+    SubdividedExceedingTheLimit(Vec<String>),
+    SplitExceededTheLimit(Vec<String>),
+    WrittenExceedingTheLimit(usize),
+    UnbalancedToken(String),
+    InvalidLen(usize),
+    InvalidOption {
+        option: &'static str,
+        value: usize,
+    },
+    MaxDepthExceeded(usize),
+    MalformedTag {
+        at: usize,
+        snippet: String,
+    },
+    VerificationFailed {
+        chunk_index: usize,
+    },
+    Io(String),
+}
+
+impl SplitError<'_> {
+    /// The best-effort chunks of [`SplitError::SplitExceededTheLimit`]. See
+    /// [`Self::into_recovered_chunks`] for [`SplitError::SubdividedExceedingTheLimit`].
+    pub fn recovered_chunks(&self) -> Option<&[String]> {
+        match self {
+            SplitError::SplitExceededTheLimit(chunks) => Some(chunks),
+            _ => None,
+        }
+    }
+
+    /// The best-effort chunks of [`SplitError::SplitExceededTheLimit`] and
+    /// [`SplitError::SubdividedExceedingTheLimit`], some of them exceed the limit
+    pub fn into_recovered_chunks(self) -> Option<Vec<String>> {
+        match self {
+            SplitError::SplitExceededTheLimit(chunks) => Some(chunks),
+            SplitError::SubdividedExceedingTheLimit(tgs) => {
+                Some(tgs.iter().map(TokenGroup::to_string).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether best-effort chunks were produced despite the error, i.e. some of them just exceed
+    /// the limit, see [`Self::into_recovered_chunks`]
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            SplitError::SplitExceededTheLimit(_)
+                | SplitError::SubdividedExceedingTheLimit(_)
+                | SplitError::WrittenExceedingTheLimit(_)
+        )
+    }
+
+    pub fn into_owned(self) -> OwnedSplitError {
+        match self {
+            SplitError::SubdivisionImpossible {
+                group,
+                open_tags,
+                min_chunk_size,
+            } => OwnedSplitError::SubdivisionImpossible {
+                group: group.to_string(),
+                open_tags: open_tags.into_iter().map(str::to_string).collect(),
+                min_chunk_size,
+            },
+            SplitError::SubdivisionImpossibleUnicode(token) => {
+                OwnedSplitError::SubdivisionImpossibleUnicode(token.to_string())
+            }
+            SplitError::SubdividedExceedingTheLimit(tgs) => {
+                OwnedSplitError::SubdividedExceedingTheLimit(
+                    tgs.iter().map(TokenGroup::to_string).collect(),
+                )
+            }
+            SplitError::SplitExceededTheLimit(chunks) => {
+                OwnedSplitError::SplitExceededTheLimit(chunks)
+            }
+            SplitError::WrittenExceedingTheLimit(count) => {
+                OwnedSplitError::WrittenExceedingTheLimit(count)
+            }
+            SplitError::UnbalancedToken(token) => {
+                OwnedSplitError::UnbalancedToken(token.to_string())
+            }
+            SplitError::InvalidLen(len) => OwnedSplitError::InvalidLen(len),
+            SplitError::InvalidOption { option, value } => {
+                OwnedSplitError::InvalidOption { option, value }
+            }
+            SplitError::MaxDepthExceeded(depth) => OwnedSplitError::MaxDepthExceeded(depth),
+            SplitError::MalformedTag { at, snippet } => OwnedSplitError::MalformedTag {
+                at,
+                snippet: snippet.to_string(),
+            },
+            SplitError::VerificationFailed { chunk_index } => {
+                OwnedSplitError::VerificationFailed { chunk_index }
+            }
+            SplitError::Io(err) => OwnedSplitError::Io(err.to_string()),
+        }
+    }
+}
+
+impl OwnedSplitError {
+    /// The best-effort chunks of [`OwnedSplitError::SplitExceededTheLimit`] and
+    /// [`OwnedSplitError::SubdividedExceedingTheLimit`], some of them exceed the limit
+    pub fn recovered_chunks(&self) -> Option<&[String]> {
+        match self {
+            OwnedSplitError::SplitExceededTheLimit(chunks)
+            | OwnedSplitError::SubdividedExceedingTheLimit(chunks) => Some(chunks),
+            _ => None,
+        }
+    }
+
+    /// Same as [`SplitError::is_recoverable`]
+    pub fn is_recoverable(&self) -> bool {
+        self.recovered_chunks().is_some()
+            || matches!(self, OwnedSplitError::WrittenExceedingTheLimit(_))
+    }
+
+    pub fn into_recovered_chunks(self) -> Option<Vec<String>> {
+        match self {
+            OwnedSplitError::SplitExceededTheLimit(chunks)
+            | OwnedSplitError::SubdividedExceedingTheLimit(chunks) => Some(chunks),
+            _ => None,
+        }
+    }
+}
+
+impl From<SplitError<'_>> for OwnedSplitError {
+    fn from(err: SplitError<'_>) -> Self {
+        err.into_owned()
+    }
+}
+
+impl std::fmt::Display for OwnedSplitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OwnedSplitError::SubdivisionImpossible {
+                group,
+                open_tags,
+                min_chunk_size,
+            } => {
+                write!(f, "Subdivision impossible: {}", group)?;
+                if !open_tags.is_empty() {
+                    write!(f, " (open tags: {})", open_tags.join(", "))?;
+                }
+                write!(f, "; needs max_chunk_size of at least {}", min_chunk_size)
+            }
+            OwnedSplitError::UnbalancedToken(token) => {
+                write!(f, "Unbalanced token: {}", token)
+            }
+            OwnedSplitError::InvalidLen(size) => {
+                write!(f, "Invalid length: {}", size)
+            }
+            OwnedSplitError::InvalidOption { option, value } => {
+                write!(f, "Invalid {}: {}", option, value)
+            }
+            OwnedSplitError::SubdivisionImpossibleUnicode(token) => {
+                write!(f, "Unicode subdivision impossible: {}", token)
+            }
+            OwnedSplitError::SubdividedExceedingTheLimit(token_groups) => {
+                write!(f, "Exceeded the limit for {token_groups:?}")
+            }
+            OwnedSplitError::SplitExceededTheLimit(tgs) => {
+                write!(f, "Split exceeded the limit for {tgs:?}")
+            }
+            OwnedSplitError::WrittenExceedingTheLimit(count) => {
+                write!(
+                    f,
+                    "Exceeded the limit in some of the {count} chunks written"
+                )
+            }
+            OwnedSplitError::MaxDepthExceeded(depth) => {
+                write!(f, "Max depth exceeded: {}", depth)
+            }
+            OwnedSplitError::MalformedTag { at, snippet } => {
+                write!(f, "Malformed tag at {}: {}", at, snippet)
+            }
+            OwnedSplitError::VerificationFailed { chunk_index } => {
+                write!(f, "Verification failed for chunk {}", chunk_index)
+            }
+            OwnedSplitError::Io(err) => {
+                write!(f, "IO error: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OwnedSplitError {}
+
+impl From<std::io::Error> for SplitError<'_> {
+    fn from(err: std::io::Error) -> Self {
+        SplitError::Io(err)
+    }
 }
 
 impl std::fmt::Display for SplitError<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            SplitError::SubdivisionImpossible(tg) => {
-                write!(f, "Subdivision impossible: {}", tg)
+            SplitError::SubdivisionImpossible {
+                group,
+                open_tags,
+                min_chunk_size,
+            } => {
+                write!(f, "Subdivision impossible: {}", group)?;
+                if !open_tags.is_empty() {
+                    write!(f, " (open tags: {})", open_tags.join(", "))?;
+                }
+                write!(f, "; needs max_chunk_size of at least {}", min_chunk_size)
             }
             SplitError::UnbalancedToken(token) => {
                 write!(f, "Unbalanced token: {}", token)
@@ -24,6 +263,9 @@ impl std::fmt::Display for SplitError<'_> {
             SplitError::InvalidLen(size) => {
                 write!(f, "Invalid length: {}", size)
             }
+            SplitError::InvalidOption { option, value } => {
+                write!(f, "Invalid {}: {}", option, value)
+            }
             SplitError::SubdivisionImpossibleUnicode(token) => {
                 write!(f, "Unicode subdivision impossible: {}", token)
             }
@@ -33,6 +275,130 @@ impl std::fmt::Display for SplitError<'_> {
             SplitError::SplitExceededTheLimit(tgs) => {
                 write!(f, "Split exceeded the limit for {tgs:?}")
             }
+            SplitError::WrittenExceedingTheLimit(count) => {
+                write!(
+                    f,
+                    "Exceeded the limit in some of the {count} chunks written"
+                )
+            }
+            SplitError::MaxDepthExceeded(depth) => {
+                write!(f, "Max depth exceeded: {}", depth)
+            }
+            SplitError::MalformedTag { at, snippet } => {
+                write!(f, "Malformed tag at {}: {}", at, snippet)
+            }
+            SplitError::VerificationFailed { chunk_index } => {
+                write!(f, "Verification failed for chunk {}", chunk_index)
+            }
+            SplitError::Io(err) => {
+                write!(f, "IO error: {}", err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clean;
+    use crate::options::{SplitOptions, WordBreak};
+
+    #[test]
+    fn test_into_owned() {
+        let err = {
+            let text = String::from("<b>unsplittable</b>");
+            let err = SplitOptions::default()
+                .max_chunk_size(10)
+                .word_break(WordBreak::Never)
+                .split(&text)
+                .unwrap_err();
+            assert!(
+                matches!(err, SplitError::SubdivisionImpossible { .. }),
+                "{err}"
+            );
+
+            let owned = err.into_owned();
+            drop(text);
+            owned
+        };
+
+        assert_eq!(
+            err,
+            OwnedSplitError::SubdivisionImpossible {
+                group: "<b>".into(),
+                open_tags: vec!["b".into()],
+                min_chunk_size: 19,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Subdivision impossible: <b> (open tags: b); needs max_chunk_size of at least 19"
+        );
+    }
+
+    #[test]
+    fn test_recovered_chunks() {
+        let html = "<p>text <a href='x'>a link too long to fit</a> tail</p>";
+        let err = crate::split(html, 24, &["a"]).unwrap_err();
+
+        let chunks = err.recovered_chunks().unwrap().to_vec();
+        assert!(chunks.iter().any(|chunk| chunk.len() > 24), "{chunks:?}");
+        assert!(chunks
+            .iter()
+            .any(|chunk| chunk.contains("<a href='x'>a link too long to fit</a>")));
+        assert_eq!(err.into_recovered_chunks(), Some(chunks));
+
+        let tg = TokenGroup::from_string(html);
+        let err = tg.subdivide(24, &["a"]).unwrap_err();
+        assert!(err.recovered_chunks().is_none());
+        let chunks = err.into_recovered_chunks().unwrap();
+        assert_eq!(clean(chunks.concat()), clean(html));
+
+        let err = SplitError::InvalidLen(0);
+        assert!(err.recovered_chunks().is_none());
+        assert!(err.into_owned().into_recovered_chunks().is_none());
+    }
+
+    #[test]
+    fn test_is_recoverable() {
+        let token = Token::CloseTag("</b>", 0);
+        let errors = [
+            (SplitError::SplitExceededTheLimit(vec![]), true),
+            (SplitError::SubdividedExceedingTheLimit(vec![]), true),
+            (SplitError::WrittenExceedingTheLimit(2), true),
+            (
+                SplitError::SubdivisionImpossible {
+                    group: TokenGroup::from_string("<b>"),
+                    open_tags: vec!["b"],
+                    min_chunk_size: 7,
+                },
+                false,
+            ),
+            (SplitError::SubdivisionImpossibleUnicode(token), false),
+            (SplitError::UnbalancedToken(token), false),
+            (SplitError::InvalidLen(0), false),
+            (
+                SplitError::InvalidOption {
+                    option: "max_tags_per_chunk",
+                    value: 0,
+                },
+                false,
+            ),
+            (SplitError::MaxDepthExceeded(65), false),
+            (
+                SplitError::MalformedTag {
+                    at: 0,
+                    snippet: "<b",
+                },
+                false,
+            ),
+            (SplitError::VerificationFailed { chunk_index: 1 }, false),
+            (SplitError::Io(std::io::ErrorKind::WriteZero.into()), false),
+        ];
+
+        for (err, is_recoverable) in errors {
+            assert_eq!(err.is_recoverable(), is_recoverable, "{err}");
+            assert_eq!(err.into_owned().is_recoverable(), is_recoverable);
         }
     }
 }
@@ -1,34 +1,266 @@
+use crate::length_mode::{entity_len, LengthMode};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Whether the text may be split at `ch`: any whitespace but the non-breaking spaces. The `&nbsp;`
+/// entity is not whitespace at all, so it's kept with the words around it.
+pub(crate) fn is_breaking_space(ch: char) -> bool {
+    ch.is_whitespace() && !matches!(ch, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+/// The longest character reference name we look for, like `&CounterClockwiseContourIntegral;`
+const MAX_ENTITY_LEN: usize = 32;
+
 pub(crate) trait SplitPosExt {
-    fn split_with_respect_to_whitespace(&self, max_len: usize) -> Option<&str>;
-    fn utf8_substring(&self, max_len: usize) -> Option<&str>;
+    fn split_with_respect_to_whitespace(&self, max_len: usize, mode: LengthMode) -> Option<&str>;
+    fn split_with_respect_to(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> Option<&str>;
+    fn split_with_respect_to_graphemes(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> Option<&str>;
+    fn split_at_whitespace(&self, max_len: usize, mode: LengthMode) -> &str;
+    fn split_at_break(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> &str;
+    fn split_at_sentence(&self, max_len: usize, mode: LengthMode) -> &str;
+    fn split_at_newline(&self, max_len: usize, mode: LengthMode) -> &str;
+    fn starts_with_url(&self) -> bool;
+    fn substring(&self, max_len: usize, mode: LengthMode) -> Option<&str>;
+    fn entity_start(&self, index: usize) -> Option<usize>;
+    fn max_atom_len(&self, mode: LengthMode, graphemes: bool) -> usize;
+    fn suffix_with_respect_to_whitespace(&self, max_len: usize, mode: LengthMode) -> &str;
 }
 
 impl SplitPosExt for str {
-    fn split_with_respect_to_whitespace(&self, max_len: usize) -> Option<&str> {
-        if max_len >= self.len() {
-            return Some(self);
-        }
+    fn split_with_respect_to_whitespace(&self, max_len: usize, mode: LengthMode) -> Option<&str> {
+        self.split_with_respect_to(max_len, mode, is_breaking_space)
+    }
 
-        let trimmed = self
-            .utf8_substring(max_len)?
-            .trim_end_matches(|ch: char| !ch.is_whitespace());
+    /// Like `split_with_respect_to_whitespace`, but the text may be cut next to any character
+    /// matching `is_break`, like a `/` of a path
+    fn split_with_respect_to(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> Option<&str> {
+        let trimmed = self.split_at_break(max_len, mode, is_break);
 
         if trimmed.is_empty() {
-            return Some(&self[..max_len]);
+            return self.substring(max_len, mode);
         }
         Some(trimmed)
     }
 
-    fn utf8_substring(&self, max_len: usize) -> Option<&str> {
+    /// Like `split_with_respect_to`, but a word is only cut between grapheme clusters; `None` if
+    /// the first one does not fit
+    fn split_with_respect_to_graphemes(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> Option<&str> {
+        let trimmed = self.split_at_break(max_len, mode, is_break);
+        if !trimmed.is_empty() {
+            return Some(trimmed);
+        }
+
+        let substring = self.substring(max_len, mode)?;
+        let end = self
+            .grapheme_indices(true)
+            .map(|(index, _)| index)
+            .chain([self.len()])
+            .take_while(|&index| index <= substring.len())
+            .last()
+            .unwrap_or_default();
+        let end = self.entity_start(end).unwrap_or(end);
+
+        (end != 0 || substring.is_empty()).then_some(&self[..end])
+    }
+
+    /// Like `split_with_respect_to_whitespace`, but never cuts a word: returns an empty string if
+    /// the first word does not fit
+    fn split_at_whitespace(&self, max_len: usize, mode: LengthMode) -> &str {
+        self.split_at_break(max_len, mode, is_breaking_space)
+    }
+
+    /// Like `split_at_whitespace`, but a word ends next to any character matching `is_break`
+    fn split_at_break(
+        &self,
+        max_len: usize,
+        mode: LengthMode,
+        is_break: impl Fn(char) -> bool,
+    ) -> &str {
+        let Some(substring) = self.substring(max_len, mode) else {
+            return "";
+        };
+        if substring.len() == self.len() {
+            return self;
+        }
+
+        // a word that ends right at the limit is fine too
+        if self[substring.len()..].starts_with(&is_break) {
+            return substring;
+        }
+
+        substring.trim_end_matches(|ch: char| !is_break(ch))
+    }
+
+    /// Whether the first word is a URL, like `https://example.com`, possibly in brackets or quotes
+    fn starts_with_url(&self) -> bool {
+        let word = self.trim_start_matches(['(', '[', '<', '"', '\'']);
+        ["http://", "https://"].iter().any(|scheme| {
+            word.get(..scheme.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+        })
+    }
+
+    /// The longest prefix that fits and ends after a sentence, i.e. after `.`, `!` or `?` followed
+    /// by whitespace; an empty string if there's no such prefix
+    fn split_at_sentence(&self, max_len: usize, mode: LengthMode) -> &str {
+        let Some(substring) = self.substring(max_len, mode) else {
+            return "";
+        };
+        if substring.len() == self.len() {
+            return self;
+        }
+
+        let mut end = 0;
+        for (index, ch) in substring.char_indices() {
+            if !matches!(ch, '.' | '!' | '?') {
+                continue;
+            }
+
+            let sentence_end = index + ch.len_utf8();
+            let Some(next) = self[sentence_end..].chars().next() else {
+                continue;
+            };
+            if is_breaking_space(next) {
+                // we take the whitespace along, if it fits, so the next chunk starts clean
+                end = sentence_end + next.len_utf8();
+                if end > substring.len() {
+                    end = sentence_end;
+                }
+            }
+        }
+
+        &self[..end]
+    }
+
+    /// The longest prefix that fits and ends right after a `\n`, so the lines are kept whole; an
+    /// empty string if even the first line doesn't fit
+    fn split_at_newline(&self, max_len: usize, mode: LengthMode) -> &str {
+        let Some(substring) = self.substring(max_len, mode) else {
+            return "";
+        };
+        if substring.len() == self.len() {
+            return self;
+        }
+
+        substring.rfind('\n').map_or("", |end| &self[..end + 1])
+    }
+
+    fn substring(&self, max_len: usize, mode: LengthMode) -> Option<&str> {
         if max_len == 0 || self.is_empty() {
             return Some("");
         }
 
-        self.char_indices()
-            .map(|(index, ch)| index + ch.len_utf8())
-            .take_while(|&next_index| next_index <= max_len)
+        let mut len = 0;
+        let mut entity_end = 0;
+        let end = self
+            .char_indices()
+            .map(|(index, ch)| {
+                // the rest of a character reference is free when it counts as a single character
+                if index >= entity_end {
+                    if ch == '&' && mode.decodes_entities() {
+                        entity_end = entity_len(&self[index..]).map_or(0, |len| index + len);
+                    }
+                    len += mode.char_len(ch);
+                }
+                (index + ch.len_utf8(), len)
+            })
+            .take_while(|&(_, len)| len <= max_len)
             .last()
-            .map(|end_index| &self[..end_index])
+            .map(|(end_index, _)| end_index)?;
+
+        // a character reference like `&amp;` is never cut, we back off to right before it
+        match self.entity_start(end) {
+            Some(0) => None,
+            Some(start) => Some(&self[..start]),
+            None => Some(&self[..end]),
+        }
+    }
+
+    /// The length of the longest piece the text can't be cut into: a character, a character
+    /// reference, or a grapheme cluster if `graphemes` is set
+    fn max_atom_len(&self, mode: LengthMode, graphemes: bool) -> usize {
+        let mut max_len = 0;
+        let mut start = 0;
+        while let Some(ch) = self[start..].chars().next() {
+            let atom_len = if ch == '&' && self.entity_start(start + 1) == Some(start) {
+                self[start..].find(';').map_or(1, |end| end + 1)
+            } else if graphemes {
+                self[start..].graphemes(true).next().map_or(1, str::len)
+            } else {
+                ch.len_utf8()
+            };
+            max_len = max_len.max(mode.measure(&self[start..start + atom_len]));
+            start += atom_len;
+        }
+        max_len
+    }
+
+    /// Where the character reference (`&...;`) that a cut at `index` would go through starts
+    fn entity_start(&self, index: usize) -> Option<usize> {
+        let bytes = self.as_bytes();
+        let is_name = |byte: &&u8| byte.is_ascii_alphanumeric() || **byte == b'#';
+
+        let name_before = bytes[..index]
+            .iter()
+            .rev()
+            .take(MAX_ENTITY_LEN)
+            .take_while(is_name)
+            .count();
+        let start = (index - name_before).checked_sub(1)?;
+        if bytes[start] != b'&' {
+            return None;
+        }
+
+        let name_after = bytes[index..]
+            .iter()
+            .take(MAX_ENTITY_LEN - name_before)
+            .take_while(is_name)
+            .count();
+        (bytes.get(index + name_after) == Some(&b';')).then_some(start)
+    }
+
+    fn suffix_with_respect_to_whitespace(&self, max_len: usize, mode: LengthMode) -> &str {
+        let mut len = 0;
+        let mut start = self.len();
+        for (index, ch) in self.char_indices().rev() {
+            len += mode.char_len(ch);
+            if len > max_len {
+                // the whitespace itself doesn't fit, but we can still start right after it
+                if is_breaking_space(ch) {
+                    return &self[index + ch.len_utf8()..];
+                }
+                return &self[start..];
+            }
+            if is_breaking_space(ch) {
+                start = index + ch.len_utf8();
+            }
+        }
+
+        self
     }
 }
 
@@ -39,20 +271,227 @@ mod tests {
     #[test]
     fn test_split_with_respect_to_whitespace() {
         let s = "hello world";
-        let trimmed = s.split_with_respect_to_whitespace(7);
+        let trimmed = s.split_with_respect_to_whitespace(7, LengthMode::Utf8);
         assert_eq!(trimmed, Some("hello "));
 
-        let trimmed = s.split_with_respect_to_whitespace(100500);
+        let trimmed = s.split_with_respect_to_whitespace(100500, LengthMode::Utf8);
         assert_eq!(trimmed, Some("hello world"));
 
         let s = "long_word_with_no_whitespace";
-        let trimmed = s.split_with_respect_to_whitespace(5);
+        let trimmed = s.split_with_respect_to_whitespace(5, LengthMode::Utf8);
         assert_eq!(trimmed, Some("long_"));
 
+        let s = "long_word_with_no_whitespace";
+        assert_eq!(s.split_at_whitespace(5, LengthMode::Utf8), "");
+        assert_eq!("a bc d".split_at_whitespace(4, LengthMode::Utf8), "a bc");
+
+        assert_eq!("a\u{a0}b".split_at_whitespace(3, LengthMode::Utf8), "");
+        assert_eq!(
+            "xx a\u{a0}b".split_at_whitespace(6, LengthMode::Utf8),
+            "xx "
+        );
+        assert_eq!(
+            "xx a\u{202f}b".split_at_whitespace(7, LengthMode::Utf8),
+            "xx "
+        );
+        assert_eq!(
+            "xx a&nbsp;b".split_at_whitespace(10, LengthMode::Utf8),
+            "xx "
+        );
+
         let s = "italic bold strikethrough ";
-        let trimmed = s.split_with_respect_to_whitespace(16);
+        let trimmed = s.split_with_respect_to_whitespace(16, LengthMode::Utf8);
         assert_eq!(trimmed, Some("italic bold "));
     }
+
+    #[test]
+    fn test_split_with_respect_to() {
+        let is_slash = |ch: char| ch == '/';
+        let s = "a/b/c/d";
+        assert_eq!(
+            s.split_with_respect_to(3, LengthMode::Utf8, is_slash),
+            Some("a/b")
+        );
+        assert_eq!(
+            s.split_with_respect_to(5, LengthMode::Utf8, is_slash),
+            Some("a/b/c")
+        );
+        assert_eq!(
+            s.split_with_respect_to(4, LengthMode::Utf8, is_slash),
+            Some("a/b/")
+        );
+        assert_eq!(
+            s.split_with_respect_to(1, LengthMode::Utf8, is_slash),
+            Some("a")
+        );
+        assert_eq!(
+            s.split_with_respect_to_whitespace(5, LengthMode::Utf8),
+            Some("a/b/c")
+        );
+
+        let s = "/usr/local/bin and more";
+        let is_path_break = |ch: char| is_breaking_space(ch) || ch == '/';
+        assert_eq!(
+            s.split_at_break(13, LengthMode::Utf8, is_path_break),
+            "/usr/local/"
+        );
+        assert_eq!(s.split_at_whitespace(13, LengthMode::Utf8), "");
+    }
+}
+
+#[cfg(test)]
+mod tests_entities {
+    use super::*;
+
+    #[test]
+    fn test_entity_start() {
+        let s = "a &amp; b";
+        assert_eq!(s.entity_start(2), None);
+        assert_eq!(s.entity_start(3), Some(2));
+        assert_eq!(s.entity_start(6), Some(2));
+        assert_eq!(s.entity_start(7), None);
+        assert_eq!("AT&T; x".entity_start(4), Some(2));
+        assert_eq!("a & b;".entity_start(4), None);
+        assert_eq!("a &amp b;".entity_start(5), None);
+    }
+
+    #[test]
+    fn test_split_keeps_entities() {
+        let s = "a &amp; b";
+        for max_len in 3..7 {
+            assert_eq!(
+                s.split_with_respect_to_whitespace(max_len, LengthMode::Utf8),
+                Some("a ")
+            );
+        }
+
+        let s = "x&#128512;y";
+        assert_eq!(s.substring(5, LengthMode::Utf8), Some("x"));
+        assert_eq!(s.substring(10, LengthMode::Utf8), Some("x&#128512;"));
+        assert_eq!("&#128512;".substring(5, LengthMode::Utf8), None);
+        assert_eq!(
+            "a&amp;b&lt;c".substring(3, LengthMode::DecodedChars),
+            Some("a&amp;b")
+        );
+    }
+
+    #[test]
+    fn test_starts_with_url() {
+        assert!("https://example.com rest".starts_with_url());
+        assert!("(HTTP://example.com)".starts_with_url());
+        assert!(!"see https://example.com".starts_with_url());
+        assert!(!"ftp://example.com".starts_with_url());
+        assert!(!"http".starts_with_url());
+    }
+
+    #[test]
+    fn test_max_atom_len() {
+        assert_eq!("ab c".max_atom_len(LengthMode::Utf8, false), 1);
+        assert_eq!("a👍".max_atom_len(LengthMode::Utf8, false), 4);
+        assert_eq!("a &amp; b".max_atom_len(LengthMode::Utf8, false), 5);
+        assert_eq!("a &amp; b".max_atom_len(LengthMode::DecodedChars, false), 1);
+        assert_eq!("e\u{301}".max_atom_len(LengthMode::Chars, false), 1);
+        assert_eq!("e\u{301}".max_atom_len(LengthMode::Chars, true), 2);
+    }
+}
+
+#[cfg(test)]
+mod tests_graphemes {
+    use super::*;
+
+    #[test]
+    fn test_split_with_respect_to_graphemes() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let s = format!("{family}{family}");
+        for max_len in family.len()..s.len() {
+            assert_eq!(
+                s.split_with_respect_to_graphemes(max_len, LengthMode::Utf8, is_breaking_space),
+                Some(family)
+            );
+        }
+        assert_eq!(
+            s.split_with_respect_to_graphemes(
+                family.len() - 1,
+                LengthMode::Utf8,
+                is_breaking_space
+            ),
+            None
+        );
+
+        let s = "cafe\u{301}s";
+        assert_eq!(
+            s.split_with_respect_to_graphemes(5, LengthMode::Chars, is_breaking_space),
+            Some("cafe\u{301}")
+        );
+        assert_eq!(
+            s.split_with_respect_to_graphemes(4, LengthMode::Chars, is_breaking_space),
+            Some("caf")
+        );
+        assert_eq!(
+            s.split_with_respect_to_whitespace(4, LengthMode::Chars),
+            Some("cafe")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_sentence {
+    use super::*;
+
+    #[test]
+    fn test_split_at_sentence() {
+        let s = "First one. Second one! Third one? Fourth";
+        assert_eq!(s.split_at_sentence(100, LengthMode::Utf8), s);
+        assert_eq!(
+            s.split_at_sentence(30, LengthMode::Utf8),
+            "First one. Second one! "
+        );
+        assert_eq!(
+            s.split_at_sentence(22, LengthMode::Utf8),
+            "First one. Second one!"
+        );
+        assert_eq!(s.split_at_sentence(9, LengthMode::Utf8), "");
+        assert_eq!("v1.2 is out".split_at_sentence(8, LengthMode::Utf8), "");
+    }
+
+    #[test]
+    fn test_split_at_newline() {
+        let s = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(s.split_at_newline(100, LengthMode::Utf8), s);
+        assert_eq!(
+            s.split_at_newline(28, LengthMode::Utf8),
+            "fn main() {\n    let x = 1;\n"
+        );
+        assert_eq!(s.split_at_newline(12, LengthMode::Utf8), "fn main() {\n");
+        assert_eq!(s.split_at_newline(11, LengthMode::Utf8), "");
+    }
+}
+
+#[cfg(test)]
+mod tests_suffix {
+    use super::*;
+
+    #[test]
+    fn test_suffix_with_respect_to_whitespace() {
+        let s = "hello big world";
+        assert_eq!(
+            s.suffix_with_respect_to_whitespace(100, LengthMode::Utf8),
+            s
+        );
+        assert_eq!(
+            s.suffix_with_respect_to_whitespace(8, LengthMode::Utf8),
+            "world"
+        );
+        assert_eq!(
+            s.suffix_with_respect_to_whitespace(9, LengthMode::Utf8),
+            "big world"
+        );
+        assert_eq!(s.suffix_with_respect_to_whitespace(4, LengthMode::Utf8), "");
+        assert_eq!(
+            "a 👍👍".suffix_with_respect_to_whitespace(4, LengthMode::Utf16),
+            "👍👍"
+        );
+    }
 }
 
 #[cfg(test)]
@@ -61,75 +500,95 @@ mod tests_utf8_slice {
 
     #[test]
     fn test_empty_string() {
-        assert_eq!("".utf8_substring(5), Some(""));
+        assert_eq!("".substring(5, LengthMode::Utf8), Some(""));
     }
 
     #[test]
     fn test_max_len_zero() {
-        assert_eq!("hello".utf8_substring(0), Some(""));
+        assert_eq!("hello".substring(0, LengthMode::Utf8), Some(""));
     }
 
     #[test]
     fn test_ascii_string() {
-        assert_eq!("hello world".utf8_substring(5), Some("hello"));
+        assert_eq!("hello world".substring(5, LengthMode::Utf8), Some("hello"));
     }
 
     #[test]
     fn test_partial_ascii_string() {
-        assert_eq!("hello".utf8_substring(2), Some("he"));
+        assert_eq!("hello".substring(2, LengthMode::Utf8), Some("he"));
     }
 
     #[test]
     fn test_non_ascii_string() {
         let s = "naïve";
-        assert_eq!(s.utf8_substring(4), Some("naï"));
+        assert_eq!(s.substring(4, LengthMode::Utf8), Some("naï"));
     }
 
     #[test]
     fn test_emoji_string() {
         let s = "👍👍👍";
-        assert_eq!(s.utf8_substring(5), Some("👍"));
-        assert_eq!(s.utf8_substring(8), Some("👍👍"));
-        assert_eq!(s.utf8_substring(12), Some("👍👍👍"));
+        assert_eq!(s.substring(5, LengthMode::Utf8), Some("👍"));
+        assert_eq!(s.substring(8, LengthMode::Utf8), Some("👍👍"));
+        assert_eq!(s.substring(12, LengthMode::Utf8), Some("👍👍👍"));
     }
 
     #[test]
     fn test_insufficient_max_len() {
         let s = "👍";
-        assert_eq!(s.utf8_substring(3), None);
+        assert_eq!(s.substring(3, LengthMode::Utf8), None);
     }
 
     #[test]
     fn test_max_len_equals_char_len() {
         let s = "👍";
-        assert_eq!(s.utf8_substring(4), Some("👍"));
+        assert_eq!(s.substring(4, LengthMode::Utf8), Some("👍"));
     }
 
     #[test]
     fn test_german_umlaut() {
         let s = "über";
-        assert_eq!(s.utf8_substring(1), None); // 'ü' is 2 bytes
-        assert_eq!(s.utf8_substring(2), Some("ü"));
-        assert_eq!(s.utf8_substring(3), Some("üb"));
+        assert_eq!(s.substring(1, LengthMode::Utf8), None); // 'ü' is 2 bytes
+        assert_eq!(s.substring(2, LengthMode::Utf8), Some("ü"));
+        assert_eq!(s.substring(3, LengthMode::Utf8), Some("üb"));
     }
 
     #[test]
     fn test_full_string() {
         let s = "こんにちは";
-        assert_eq!(s.utf8_substring(15), Some(s));
+        assert_eq!(s.substring(15, LengthMode::Utf8), Some(s));
     }
 
     #[test]
     fn test_partial_multibyte_char() {
         let s = "こんにちは";
-        assert_eq!(s.utf8_substring(2), None); // No character fits in 2 bytes
-        assert_eq!(s.utf8_substring(3), Some("こ")); // First character is 3 bytes
-        assert_eq!(s.utf8_substring(4), Some("こ")); // "こ" fits within 4 bytes
+        assert_eq!(s.substring(2, LengthMode::Utf8), None); // No character fits in 2 bytes
+        assert_eq!(s.substring(3, LengthMode::Utf8), Some("こ")); // First character is 3 bytes
+        assert_eq!(s.substring(4, LengthMode::Utf8), Some("こ")); // "こ" fits within 4 bytes
     }
 
     #[test]
     fn test_max_len_longer_than_string() {
         let s = "hello";
-        assert_eq!(s.utf8_substring(10), Some("hello"));
+        assert_eq!(s.substring(10, LengthMode::Utf8), Some("hello"));
+    }
+
+    #[test]
+    fn test_utf16_substring() {
+        let s = "a👍b";
+        assert_eq!(s.substring(1, LengthMode::Utf16), Some("a"));
+        assert_eq!(s.substring(2, LengthMode::Utf16), Some("a"));
+        assert_eq!(s.substring(3, LengthMode::Utf16), Some("a👍"));
+        assert_eq!("👍".substring(1, LengthMode::Utf16), None);
+    }
+
+    #[test]
+    fn test_chars_substring() {
+        let s = "こんにちは world";
+        assert_eq!(s.substring(2, LengthMode::Chars), Some("こん"));
+        assert_eq!(s.substring(7, LengthMode::Chars), Some("こんにちは w"));
+        assert_eq!(
+            s.split_with_respect_to_whitespace(8, LengthMode::Chars),
+            Some("こんにちは ")
+        );
     }
 }
@@ -0,0 +1,136 @@
+//! Fuzzing support, see `fuzz/fuzz_targets/split.rs`
+
+use crate::error::SplitError;
+use crate::{clean, split};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+const TAGS: &[&str] = &[
+    "<b>",
+    "<i>",
+    "<s>",
+    "<u>",
+    "<code>",
+    "<pre>",
+    "<blockquote>",
+    r#"<span class="tg-spoiler">"#,
+    r#"<a href="http://www.example.com/?a=1&amp;b=2">"#,
+    r#"<tg-emoji emoji-id="5368324170671202286">"#,
+];
+
+const TEXTS: &[&str] = &[
+    "word",
+    " ",
+    "\n",
+    "two words",
+    "a &lt; b",
+    "&amp;",
+    "&#128512;",
+    "👍",
+    "👨\u{200d}👩\u{200d}👧",
+    "e\u{301}",
+    "Sentence one. Sentence two!",
+];
+
+const STRAY_TEXT: &str = "a < b";
+
+const LEAVES: &[&str] = &["<br>", "<br/>", "<img src='x.png'/>", "<!-- a > b -->"];
+
+/// Plausibly nested HTML: balanced formatting tags around text, entities, emoji, void elements
+/// and comments, maybe with a stray `<` at the end. One before a tag would make a tag of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzHtml(pub String);
+
+impl FuzzHtml {
+    const MAX_DEPTH: usize = 8;
+
+    fn write_nodes(u: &mut Unstructured, html: &mut String, depth: usize) -> Result<()> {
+        let len = u.int_in_range(0..=4)?;
+        for _ in 0..len {
+            match u.int_in_range(0..=3)? {
+                0 if depth < Self::MAX_DEPTH => {
+                    let open = *u.choose(TAGS)?;
+                    html.push_str(open);
+                    Self::write_nodes(u, html, depth + 1)?;
+
+                    let name = &open[1..open.find([' ', '>']).unwrap_or(open.len())];
+                    html.push_str(&format!("</{name}>"));
+                }
+                1 => html.push_str(u.choose(LEAVES)?),
+                _ => html.push_str(u.choose(TEXTS)?),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Arbitrary<'a> for FuzzHtml {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut html = String::new();
+        Self::write_nodes(u, &mut html, 0)?;
+        if u.arbitrary()? {
+            html.push_str(STRAY_TEXT);
+        }
+        Ok(Self(html))
+    }
+}
+
+/// The input of the split fuzz target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitInput {
+    pub html: FuzzHtml,
+    pub max_chunk_size: usize,
+}
+
+impl<'a> Arbitrary<'a> for SplitInput {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            max_chunk_size: u.int_in_range(1..=512)?,
+            html: FuzzHtml::arbitrary(u)?,
+        })
+    }
+}
+
+/// Splits `html` and checks that the chunks render the same text. The input is balanced, so the
+/// only errors allowed are about the limit being too small for it. Panics on any other error, if
+/// the chunks don't render the same text, or if splitting panics itself.
+pub fn check_split(html: &str, max_chunk_size: usize) {
+    let chunks = match split(html, max_chunk_size, &[]) {
+        Ok(chunks) | Err(SplitError::SplitExceededTheLimit(chunks)) => chunks,
+        Err(
+            SplitError::SubdivisionImpossible { .. }
+            | SplitError::SubdivisionImpossibleUnicode(_)
+            | SplitError::SubdividedExceedingTheLimit(_),
+        ) => return,
+        Err(err) => panic!("{max_chunk_size}: {err} for {html:?}"),
+    };
+
+    assert_eq!(
+        clean(chunks.concat()),
+        clean(html),
+        "{max_chunk_size}: {chunks:?}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary_inputs() -> testresult::TestResult {
+        for seed in 0..256u64 {
+            // a linear congruential generator is random enough to drive the input structure
+            let mut state = seed;
+            let bytes = (0..1024)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    (state >> 56) as u8
+                })
+                .collect::<Vec<_>>();
+            let input = SplitInput::arbitrary(&mut Unstructured::new(&bytes))?;
+            check_split(&input.html.0, input.max_chunk_size);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use crate::tokenizer::Tokenizer;
+use unicode_width::UnicodeWidthChar;
+
+/// The unit `max_chunk_size` is measured in
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LengthMode {
+    /// UTF-8 bytes, i.e. `str::len`
+    #[default]
+    Utf8,
+    /// UTF-16 code units, the way Telegram counts message length and entity offsets
+    Utf16,
+    /// Unicode scalar values, i.e. `str::chars().count()`
+    Chars,
+    /// Unicode scalar values of the text only, the tags and the comments are free. For the
+    /// messengers counting only what the reader sees, so a character reference like `&amp;`
+    /// counts as a single character.
+    VisibleText,
+    /// Unicode scalar values, with a character reference like `&amp;` or `&#128512;` counted as
+    /// the single character it stands for. The output is left encoded.
+    DecodedChars,
+    /// Terminal cells, with the wide characters like CJK ones counted as two and the zero-width
+    /// ones as none. The tags count by their width too.
+    DisplayWidth,
+}
+
+/// The named character references counted as a single character, the numeric ones are always
+/// recognized
+const NAMED_ENTITIES: &[&str] = &[
+    "amp", "lt", "gt", "quot", "apos", "nbsp", "copy", "reg", "trade", "hellip", "mdash", "ndash",
+    "laquo", "raquo", "lsquo", "rsquo", "ldquo", "rdquo", "bull", "middot", "deg", "times",
+    "divide", "euro", "pound", "yen", "cent", "sect", "para", "plusmn",
+];
+
+/// The byte length of the character reference `text` starts with, like `&amp;` or `&#x1F600;`
+pub(crate) fn entity_len(text: &str) -> Option<usize> {
+    let rest = text.strip_prefix('&')?;
+    let (name, _) = rest.split_once(';')?;
+
+    let is_known = match name.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|ch| ch.is_ascii_hexdigit()),
+            None => !number.is_empty() && number.chars().all(|ch| ch.is_ascii_digit()),
+        },
+        None => NAMED_ENTITIES.contains(&name),
+    };
+
+    is_known.then_some(name.len() + 2)
+}
+
+/// The number of characters with the character references decoded
+fn decoded_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        let skip = match ch {
+            '&' => entity_len(rest).unwrap_or(1),
+            _ => ch.len_utf8(),
+        };
+        rest = &rest[skip..];
+        len += 1;
+    }
+    len
+}
+
+impl LengthMode {
+    pub fn measure(&self, text: &str) -> usize {
+        match self {
+            LengthMode::Utf8 => text.len(),
+            LengthMode::Utf16 => text.encode_utf16().count(),
+            LengthMode::Chars => text.chars().count(),
+            LengthMode::VisibleText => Tokenizer::new(text).map(|token| token.len_in(*self)).sum(),
+            LengthMode::DecodedChars => decoded_len(text),
+            // the sum of the characters, so that a cut between them adds up
+            LengthMode::DisplayWidth => text.chars().map(|ch| self.char_len(ch)).sum(),
+        }
+    }
+
+    /// Whether a character reference counts as a single character
+    pub(crate) fn decodes_entities(&self) -> bool {
+        matches!(self, LengthMode::VisibleText | LengthMode::DecodedChars)
+    }
+
+    /// The length of the text token `text`
+    pub(crate) fn text_len(&self, text: &str) -> usize {
+        match self {
+            LengthMode::VisibleText => decoded_len(text),
+            _ => self.measure(text),
+        }
+    }
+
+    pub(crate) fn char_len(&self, ch: char) -> usize {
+        match self {
+            LengthMode::Utf8 => ch.len_utf8(),
+            LengthMode::Utf16 => ch.len_utf16(),
+            LengthMode::Chars | LengthMode::VisibleText | LengthMode::DecodedChars => 1,
+            LengthMode::DisplayWidth => ch.width().unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure() {
+        let text = "a👍ü";
+        assert_eq!(LengthMode::Utf8.measure(text), 7);
+        assert_eq!(LengthMode::Utf16.measure(text), 4);
+        assert_eq!(LengthMode::Chars.measure(text), 3);
+        assert_eq!(LengthMode::VisibleText.measure(text), 3);
+        assert_eq!(
+            LengthMode::VisibleText.measure("<b>a👍</b><!-- x --><i>ü</i>"),
+            3
+        );
+    }
+
+    #[test]
+    fn test_measure_display_width() {
+        assert_eq!(LengthMode::DisplayWidth.measure("ab"), 2);
+        assert_eq!(LengthMode::DisplayWidth.measure("漢字"), 4);
+        assert_eq!(LengthMode::DisplayWidth.measure("<b>한</b>"), 9);
+        assert_eq!(LengthMode::DisplayWidth.measure("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn test_measure_entities() {
+        let text = "a &amp; b&#128512;&#x1F600;&unknown;";
+        assert_eq!(LengthMode::DecodedChars.measure(text), 16);
+        assert_eq!(LengthMode::Chars.measure(text), text.len());
+        assert_eq!(LengthMode::VisibleText.measure("<b>&lt;b&gt;</b>"), 3);
+        assert_eq!(entity_len("&amp; b"), Some(5));
+        assert_eq!(entity_len("&#;"), None);
+        assert_eq!(entity_len("& b;"), None);
+    }
+}
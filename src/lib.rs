@@ -1,50 +1,46 @@
-use crate::error::SplitError;
+use crate::error::{OwnedSplitError, SplitError};
+use crate::ext::SplitPosExt;
+use crate::length_mode::LengthMode;
+use crate::options::{ChunkMetadata, SplitOptions, SplitStats};
+use crate::split_iter::SplitIter;
 use crate::token::Token;
-use crate::token_group::TokenGroup;
-use crate::tokenizer::Tokenizer;
+use crate::token_group::{RootTokenGroups, TokenGroup};
+use std::borrow::Cow;
+use std::ops::Range;
 
 pub mod error;
 mod ext;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod length_mode;
+pub mod options;
+pub mod prepared;
+mod selector;
+pub mod split_iter;
+pub mod splitter;
 #[cfg(test)]
 mod test_data;
 pub mod token;
 pub mod token_group;
-mod tokenizer;
-
-fn prepare_token_groups(html: &str) -> Result<Vec<TokenGroup>, SplitError> {
-    // Since most of the html text this splitter is supposed to split is markdown-like formatting
-    // converted to html, there will be no root element. Most of the tags will be like
-    // `<b>something</b>`, or at worst `Some text <b>something <i>italic</i></b> blah blah`.
-    // So, instead of trying to stuff the max possible amount of text into a single chunk, we prefer
-    // to put it into the next one. Apart from that, all links, or bold titles, and other whatnot
-    // will be moved to the next chunk if they don't fit. I guess it's better for messengers
-    // where you would not like to read split titles.
-    let mut stack = vec![];
-    let mut token_group = TokenGroup::default();
-    let mut token_groups = vec![];
-
-    for token in Tokenizer::new(html) {
-        token_group.push(token);
-
-        match token {
-            Token::OpenTag(_, _) => stack.push(token),
-            Token::CloseTag(_, _) => {
-                stack.pop().ok_or(SplitError::UnbalancedToken(token))?;
-            }
-            _ => {}
-        }
-
-        if stack.is_empty() {
-            token_groups.push(token_group);
-            token_group = TokenGroup::default();
-        }
-    }
+pub mod tokenizer;
 
-    if !stack.is_empty() {
-        return Err(SplitError::UnbalancedToken(stack.pop().unwrap()));
-    }
-
-    Ok(token_groups)
+/// Tokenizes `html` into its root-level groups, the units [`split`] packs into chunks, for the
+/// callers implementing their own chunking policy. Fails on the first unbalanced tag.
+///
+/// ```
+/// use dumb_html_splitter::prepare_token_groups;
+///
+/// let html = "<p>one</p>\n<p>two <b>three</b></p>";
+/// let groups = prepare_token_groups(html)?;
+/// let rendered = groups.iter().map(ToString::to_string).collect::<Vec<_>>();
+/// assert_eq!(rendered, ["<p>one</p>", "\n", "<p>two <b>three</b></p>"]);
+/// for group in &groups {
+///     println!("{} bytes: {group}", group.len);
+/// }
+/// # Ok::<(), dumb_html_splitter::error::SplitError>(())
+/// ```
+pub fn prepare_token_groups(html: &str) -> Result<Vec<TokenGroup<'_>>, SplitError<'_>> {
+    RootTokenGroups::new(html, LengthMode::default()).collect()
 }
 
 #[cfg(test)]
@@ -62,59 +58,300 @@ pub fn split<'a>(
     max_chunk_size: usize,
     no_split: &[&str],
 ) -> Result<Vec<String>, SplitError<'a>> {
-    // We'd like to get off without involving subdividing token groups itself.
-    // If we can open a new chunk, we do it. If the token group is larger than max_chunk_size, only
-    // then do we call subdivide on it.
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split(text)
+}
 
-    let mut chunks = vec![];
-    let mut chunk = String::new();
+/// Same as [`split`], but the chunks are kept as token groups
+pub fn split_groups<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_groups(text)
+}
 
-    let mut has_exceeded = false;
+/// Same as [`split`], but the `no_split` tags can be any strings, like a `Vec<String>`
+pub fn split_with_tags<'a, I, S>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: I,
+) -> Result<Vec<String>, SplitError<'a>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split_tags(no_split)
+        .split(text)
+}
 
-    for tg in prepare_token_groups(text)? {
-        if chunk.len() + tg.len <= max_chunk_size {
-            chunk.push_str(&tg.to_string());
-            continue;
-        }
+/// Same as [`split`], but for the groups of [`prepare_token_groups`], so that a document can be
+/// split several times without tokenizing it again
+pub fn split_prepared<'a>(
+    groups: &[TokenGroup<'a>],
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<String>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_prepared(groups)
+}
 
-        if tg.len <= max_chunk_size {
-            chunks.push(chunk);
-            chunks.push(tg.to_string());
-            chunk = String::new();
-            continue;
-        }
+/// Same as [`split`], but `max_chunk_size` is measured in `length_mode` units
+pub fn split_with_length_mode<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+    length_mode: LengthMode,
+) -> Result<Vec<String>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .length_mode(length_mode)
+        .split(text)
+}
 
-        if !chunk.is_empty() {
-            chunks.push(chunk);
-            chunk = String::new();
-        }
+/// Same as [`split`], but the chunks that are a single slice of `text` are borrowed, see
+/// [`SplitOptions::split_cow`]
+pub fn split_cow<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<Cow<'a, str>>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_cow(text)
+}
 
-        let tgs = match tg.subdivide(max_chunk_size, no_split) {
-            Ok(tgs) => tgs,
-            Err(SplitError::SubdividedExceedingTheLimit(tgs)) => {
-                has_exceeded = true;
-                tgs
-            }
-            Err(err) => return Err(err),
-        };
+/// Same as [`split`], but every chunk comes with the byte range of `text` it was cut from, see
+/// [`SplitOptions::split_with_ranges`]
+pub fn split_with_ranges<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<(String, Range<usize>)>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_with_ranges(text)
+}
 
-        for tg in tgs {
-            chunks.push(tg.to_string());
-        }
-    }
+/// Same as [`split`], but every chunk is passed through `f` along with its index, see
+/// [`SplitOptions::split_map`]
+pub fn split_map<'a, F, E>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+    f: F,
+) -> Result<Vec<String>, E>
+where
+    F: FnMut(usize, String) -> Result<String, E>,
+    E: From<SplitError<'a>>,
+{
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_map(text, f)
+}
+
+/// Writes the chunks [`split`] would produce to `writer`, separated by `separator`, without
+/// collecting them; returns their number, see [`SplitOptions::split_to_writer`]
+pub fn split_to_writer<'a, W: std::io::Write>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+    separator: &[u8],
+    writer: &mut W,
+) -> Result<usize, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_to_writer(text, separator, writer)
+}
+
+/// Splits every document of `docs` the same way [`split`] does, in parallel with the `rayon`
+/// feature
+pub fn split_many(
+    docs: &[&str],
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Vec<Result<Vec<String>, OwnedSplitError>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_many(docs)
+}
+
+/// Same as [`split`], along with the numbers about the chunks, see
+/// [`SplitOptions::split_with_stats`]
+pub fn split_with_stats<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<(Vec<String>, SplitStats), SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_with_stats(text)
+}
+
+/// Same as [`split`], but every chunk comes with the `no_split` elements it contains whole, see
+/// [`SplitOptions::split_with_metadata`]
+pub fn split_with_metadata<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<(String, ChunkMetadata)>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_with_metadata(text)
+}
+
+/// Same as [`split`], but every chunk comes with the names of the tags carried into the
+/// following chunk, see [`SplitOptions::split_with_open_stacks`]
+pub fn split_with_open_stacks<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<Vec<(String, Vec<String>)>, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_with_open_stacks(text)
+}
+
+/// The number of chunks [`split`] would produce, without rendering them
+pub fn count_chunks<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<usize, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .count_chunks(text)
+}
+
+/// The smallest `max_chunk_size` [`split`] succeeds with for `text`
+pub fn compute_minimum_chunk_size<'a>(
+    text: &'a str,
+    no_split: &[&str],
+) -> Result<usize, SplitError<'a>> {
+    SplitOptions::default()
+        .no_split(no_split)
+        .minimum_chunk_size(text)
+}
+
+/// Whether [`split`] succeeds without any chunk exceeding the limit, see
+/// [`SplitOptions::can_split`]
+pub fn can_split<'a>(
+    text: &'a str,
+    max_chunk_size: usize,
+    no_split: &[&str],
+) -> Result<bool, SplitError<'a>> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .can_split(text)
+}
 
-    if !chunk.is_empty() {
-        chunks.push(chunk);
+/// Checks that every tag of `html` is closed in order, without splitting it. Reports the first
+/// unbalanced tag, the same one [`split`] would.
+pub fn validate_balanced<'a>(html: &'a str) -> Result<(), SplitError<'a>> {
+    RootTokenGroups::new(html, LengthMode::default()).try_for_each(|tg| tg.map(|_| ()))
+}
+
+/// Reassembles the chunks of [`split`], removing the close tags added at the end of a chunk and
+/// the open tags reopened at the start of the next one.
+///
+/// The chunks are text, so a close tag followed by the very same open tag at the boundary is
+/// taken for such a seam, even if it's two sibling elements, like `<p>a</p>` and `<p>b</p>`
+/// that ended up in two chunks. See [`join_groups`] for the chunks of [`split_groups`], which
+/// tell the siblings apart.
+///
+/// ```
+/// use dumb_html_splitter::{join, split};
+///
+/// let html = "<b>bold text</b> and <i>italic text</i>";
+/// let chunks = split(html, 16, &[])?;
+/// assert_eq!(chunks, ["<b>bold text</b>", " and ", "<i>italic </i>", "<i>text</i>"]);
+/// assert_eq!(join(&chunks), html);
+/// # Ok::<(), dumb_html_splitter::error::SplitError>(())
+/// ```
+pub fn join<S: AsRef<str>>(chunks: &[S]) -> String {
+    let chunks = chunks
+        .iter()
+        .map(|chunk| TokenGroup::from_string_literally(chunk.as_ref()))
+        .collect::<Vec<_>>();
+    join_by(&chunks, |closed, open| closed.as_text() == open.as_text())
+}
+
+/// Same as [`join`], but for the chunks of [`split_groups`]. Only an element cut between two
+/// chunks makes a seam, the sibling elements are kept as is.
+pub fn join_groups(chunks: &[TokenGroup]) -> String {
+    join_by(chunks, |closed, open| closed.is_same_source(open))
+}
+
+fn join_by(chunks: &[TokenGroup], is_same: impl Fn(&Token, &Token) -> bool) -> String {
+    let mut joined = String::new();
+    let mut start = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let (end, next_start) = match chunks.get(index + 1) {
+            Some(next) => chunk.seam_with(next, &is_same),
+            None => (chunk.tokens.len(), 0),
+        };
+        chunk.write_tokens_to(start.min(end)..end, &mut joined);
+        start = next_start;
     }
+    joined
+}
 
-    if has_exceeded {
-        return Err(SplitError::SplitExceededTheLimit(chunks));
+/// The text of the document without the tags and the comments, e.g. a plain-text fallback for
+/// the chunks
+pub fn strip_tags(html: &str) -> String {
+    TokenGroup::from_string(html).to_plain_text()
+}
+
+/// Splits the text of the document, with the tags and the comments dropped, at the whitespace
+/// where possible. A character that doesn't fit into `max_chunk_size` gets a chunk of its own.
+pub fn split_plain(html: &str, max_chunk_size: usize) -> Vec<String> {
+    let text = strip_tags(html);
+    let mut rest = text.as_str();
+    let mut chunks = vec![];
+    while let Some(ch) = rest.chars().next() {
+        let chunk = rest
+            .split_with_respect_to_whitespace(max_chunk_size, LengthMode::Utf8)
+            .filter(|chunk| !chunk.is_empty())
+            .unwrap_or(&rest[..ch.len_utf8()]);
+        chunks.push(chunk.to_string());
+        rest = &rest[chunk.len()..];
     }
+    chunks
+}
 
-    Ok(chunks)
+/// Lazily yields the same chunks [`split`] would produce.
+///
+/// A token group that can't be subdivided within the limit is reported in place as
+/// [`SplitError::SubdividedExceedingTheLimit`], and the iteration continues after it.
+pub fn split_iter<'a>(text: &'a str, max_chunk_size: usize, no_split: &[&str]) -> SplitIter<'a> {
+    SplitOptions::default()
+        .max_chunk_size(max_chunk_size)
+        .no_split(no_split)
+        .split_iter(text)
 }
 
-#[cfg(test)]
+/// The text of `html` with the tags removed, the way a browser would show it
+#[cfg(any(test, feature = "arbitrary"))]
 fn clean(html: impl AsRef<str>) -> String {
     use ammonia::Builder;
     use std::collections::HashSet;
@@ -129,7 +366,7 @@ fn clean(html: impl AsRef<str>) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_data::LONG_HTML;
+    use crate::test_data::{LONG_HTML, SHORT_HTML};
     use testresult::TestResult;
 
     #[test]
@@ -144,6 +381,44 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_split_prepared() -> TestResult {
+        let groups = prepare_token_groups(LONG_HTML)?;
+        for chunk_size in (100..1500).step_by(37) {
+            assert_eq!(
+                split_prepared(&groups, chunk_size, &["a"])?,
+                split(LONG_HTML, chunk_size, &["a"])?
+            );
+        }
+
+        let options = SplitOptions::default()
+            .max_chunk_size(300)
+            .min_chunk_size(100)
+            .distribution(options::Distribution::Balanced);
+        assert_eq!(options.split_prepared(&groups)?, options.split(LONG_HTML)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_max_chunk_size() {
+        assert!(matches!(
+            split("hi", 0, &[]),
+            Err(SplitError::InvalidLen(0))
+        ));
+        assert!(matches!(split("", 0, &[]), Err(SplitError::InvalidLen(0))));
+        assert!(matches!(
+            split_groups("<b>hi</b>", 0, &[]),
+            Err(SplitError::InvalidLen(0))
+        ));
+        assert!(matches!(
+            count_chunks("hi", 0, &[]),
+            Err(SplitError::InvalidLen(0))
+        ));
+        let mut iter = split_iter("hi", 0, &[]);
+        assert!(matches!(iter.next(), Some(Err(SplitError::InvalidLen(0)))));
+        assert!(iter.next().is_none());
+    }
+
     #[test]
     fn test_split_plain_text() {
         let text = "This is a simple plain text without any HTML tags.";
@@ -199,4 +474,572 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_split_groups() -> TestResult {
+        for chunk_size in (60..4096).step_by(7) {
+            match split(LONG_HTML, chunk_size, &["a"]) {
+                Ok(chunks) => {
+                    let tgs = split_groups(LONG_HTML, chunk_size, &["a"])?;
+                    assert_eq!(
+                        tgs.iter().map(TokenGroup::to_string).collect::<Vec<_>>(),
+                        chunks
+                    );
+                    assert_eq!(
+                        SplitOptions::default()
+                            .max_chunk_size(chunk_size)
+                            .no_split(&["a"])
+                            .split(LONG_HTML)?,
+                        chunks
+                    );
+                }
+                Err(err) => assert_eq!(
+                    split_groups(LONG_HTML, chunk_size, &["a"])
+                        .err()
+                        .map(SplitError::into_owned),
+                    Some(err.into_owned())
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_with_open_stacks() -> TestResult {
+        let chunks = split_with_open_stacks("<b><i>bold italic</i> bold</b><p>next</p>", 24, &[])?;
+        assert_eq!(
+            chunks,
+            vec![
+                ("<b><i>bold </i></b>".into(), vec!["b".into(), "i".into()]),
+                ("<b><i>italic</i></b>".into(), vec!["b".into()]),
+                ("<b> bold</b>".into(), vec![]),
+                ("<p>next</p>".into(), vec![]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_minimum_chunk_size() -> TestResult {
+        let min_chunk_size = compute_minimum_chunk_size(SHORT_HTML, &["a"])?;
+        assert_eq!(min_chunk_size, 54);
+        split(SHORT_HTML, min_chunk_size, &["a"])?;
+        assert!(split(SHORT_HTML, min_chunk_size - 1, &["a"]).is_err());
+
+        let min_chunk_size = compute_minimum_chunk_size(LONG_HTML, &["a"])?;
+        split(LONG_HTML, min_chunk_size, &["a"])?;
+        assert!(split(LONG_HTML, min_chunk_size - 1, &["a"]).is_err());
+
+        assert_eq!(compute_minimum_chunk_size("", &[])?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_empty_tag() -> TestResult {
+        assert_eq!(split("a<>b", 4096, &[])?, vec!["a<>b"]);
+        assert_eq!(split("<b>a<>b</b> c", 12, &[])?, vec!["<b>a<>b</b>", " c"]);
+        assert_eq!(
+            split("<b>a<>b</b>", 8, &[])?,
+            vec!["<b>a</b>", "<b><</b>", "<b>></b>", "<b>b</b>"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_with_tags() -> TestResult {
+        let html = "<b>bold</b> and <a href='x'>a link</a> and <i>italic text</i>";
+        let no_split = vec!["a".to_string(), "i".to_string()];
+        for max_chunk_size in 24..48 {
+            let expected = split(html, max_chunk_size, &["a", "i"])?;
+            assert_eq!(split_with_tags(html, max_chunk_size, &no_split)?, expected);
+            assert_eq!(
+                split_with_tags(html, max_chunk_size, no_split.clone())?,
+                expected
+            );
+            assert_eq!(split_with_tags(html, max_chunk_size, ["a", "i"])?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_no_split_wildcard() -> TestResult {
+        let html = "some text <b><i>bold italic</i></b> and <s>more</s>";
+        assert_eq!(
+            split(html, 32, &["*"])?,
+            vec![
+                "some text ",
+                "<b><i>bold italic</i></b>",
+                " and <s>more</s>"
+            ]
+        );
+
+        let tg = TokenGroup::from_string("<b><i>bold italic</i></b>");
+        assert!(matches!(
+            tg.subdivide(24, &["*"]),
+            Err(SplitError::SubdividedExceedingTheLimit(_))
+        ));
+        assert_eq!(tg.subdivide(24, &[])?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_keeps_non_breaking_spaces() -> TestResult {
+        let html = "<b>word a\u{a0}b</b>";
+        assert_eq!(
+            split(html, 15, &[])?,
+            vec!["<b>word </b>", "<b>a\u{a0}b</b>"]
+        );
+
+        let html = "<b>word 10&nbsp;kg</b>";
+        assert_eq!(
+            split(html, 20, &[])?,
+            vec!["<b>word </b>", "<b>10&nbsp;kg</b>"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_cow() -> TestResult {
+        let text = "Plain text without any formatting, split into a few chunks";
+        let chunks = split_cow(text, 24, &[])?;
+        assert_eq!(chunks, split(text, 24, &[])?);
+        assert!(chunks.iter().all(|chunk| matches!(chunk, Cow::Borrowed(_))));
+
+        let html = "<b>bold words</b> and <i>italic</i> text";
+        let chunks = split_cow(html, 16, &[])?;
+        assert_eq!(chunks, split(html, 16, &[])?);
+        assert!(matches!(chunks[0], Cow::Owned(_)));
+        assert!(chunks.iter().any(|chunk| matches!(chunk, Cow::Borrowed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_can_split() -> TestResult {
+        let min_chunk_size = compute_minimum_chunk_size(SHORT_HTML, &["a"])?;
+        assert!(!can_split(SHORT_HTML, min_chunk_size - 1, &["a"])?);
+        assert!(can_split(SHORT_HTML, min_chunk_size, &["a"])?);
+        assert!(can_split(SHORT_HTML, 4096, &["a"])?);
+
+        let html = "<p>text <a href='x'>a link too long to fit</a> tail</p>";
+        assert!(!can_split(html, 24, &["a"])?);
+        assert!(can_split(html, 24, &[])?);
+
+        assert!(can_split("</b>", 24, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_regressions() {
+        // the inputs the fuzz target found, see `fuzz/fuzz_targets/split.rs`
+        let sample = include_str!("./test_data/sample1.html");
+        for max_chunk_size in (1..2048).step_by(13) {
+            let chunks = match split(sample, max_chunk_size, &[]) {
+                Ok(chunks) | Err(SplitError::SplitExceededTheLimit(chunks)) => chunks,
+                Err(
+                    SplitError::SubdivisionImpossible { .. }
+                    | SplitError::SubdivisionImpossibleUnicode(_),
+                ) => continue,
+                Err(err) => panic!("{max_chunk_size}: {err}"),
+            };
+            assert_eq!(clean(chunks.concat()), clean(sample), "{max_chunk_size}");
+        }
+    }
+
+    #[test]
+    fn test_count_chunks() -> TestResult {
+        for chunk_size in (60..4096).step_by(7) {
+            match split(LONG_HTML, chunk_size, &[]) {
+                Ok(chunks) => assert_eq!(count_chunks(LONG_HTML, chunk_size, &[])?, chunks.len()),
+                Err(err) => assert_eq!(
+                    count_chunks(LONG_HTML, chunk_size, &[]).map_err(SplitError::into_owned),
+                    Err(err.into_owned())
+                ),
+            }
+        }
+
+        // the chunks trimmed away are not counted
+        let html = "<p>aaaaaaaa</p>          <p>bbbbbbbb</p>";
+        let options = SplitOptions::default().max_chunk_size(10).trim_chunks(true);
+        let chunks = options.split(html)?;
+        assert_eq!(options.count_chunks(html)?, chunks.len());
+        assert_eq!(options.split_with_stats(html)?.0, chunks);
+        assert_eq!(options.split_groups(html)?.len(), chunks.len());
+        for chunk_size in (60..4096).step_by(7) {
+            let options = options.clone().max_chunk_size(chunk_size);
+            if let Ok(chunks) = options.split(LONG_HTML) {
+                assert_eq!(options.count_chunks(LONG_HTML)?, chunks.len());
+                assert_eq!(options.split_with_stats(LONG_HTML)?.0, chunks);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_keeps_entities() -> TestResult {
+        for (html, entity) in [("a &amp; b", "&amp;"), ("x &#128512; y", "&#128512;")] {
+            for max_chunk_size in entity.len() + 1..html.len() {
+                let chunks = split(html, max_chunk_size, &[])?;
+                assert_eq!(chunks.concat(), html);
+                assert!(
+                    chunks.iter().any(|chunk| chunk.contains(entity)),
+                    "{chunks:?}"
+                );
+            }
+        }
+
+        let chunks = split("<b>x&#128512;y</b>", 16, &[])?;
+        assert_eq!(chunks, vec!["<b>x</b>", "<b>&#128512;</b>", "<b>y</b>"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_decoded_chars() -> TestResult {
+        let text = "&amp;".repeat(10);
+        let result = split_with_length_mode(&text, 10, &[], LengthMode::DecodedChars)?;
+        assert_eq!(result, vec![text.clone()]);
+
+        let result = split_with_length_mode(&text, 4, &[], LengthMode::DecodedChars)?;
+        assert_eq!(
+            result,
+            vec!["&amp;".repeat(4), "&amp;".repeat(4), "&amp;".repeat(2)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_balanced() -> TestResult {
+        validate_balanced(LONG_HTML)?;
+        validate_balanced("text <b>bold <i>italic</i></b><!-- <b> -->")?;
+
+        let err = validate_balanced("<b>bold <i>italic</b>").unwrap_err();
+        assert!(matches!(
+            err,
+            SplitError::UnbalancedToken(Token::CloseTag("</b>", 17))
+        ));
+
+        let err = validate_balanced("<b>bold</b><i>italic").unwrap_err();
+        assert!(matches!(
+            err,
+            SplitError::UnbalancedToken(Token::OpenTag("<i>", 11))
+        ));
+
+        let err = validate_balanced("<b>bold</b></i>").unwrap_err();
+        assert!(matches!(
+            err,
+            SplitError::UnbalancedToken(Token::CloseTag("</i>", 11))
+        ));
+
+        for html in [
+            "<b>bold <i>italic</b>",
+            "<b>bold</b><i>italic",
+            "<b>bold</b></i>",
+        ] {
+            assert!(split(html, 4096, &[]).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join() -> TestResult {
+        for chunk_size in (60..4096).step_by(7) {
+            let Ok(chunks) = split(LONG_HTML, chunk_size, &[]) else {
+                continue;
+            };
+            assert_eq!(join(&chunks), LONG_HTML, "{chunk_size}");
+        }
+
+        let html = "<p><b>bold <i>italic</i> words</b> and more text</p>";
+        for chunk_size in 8..=html.len() {
+            let Ok(chunks) = split(html, chunk_size, &[]) else {
+                continue;
+            };
+            assert_eq!(join(&chunks), html, "{chunk_size}");
+        }
+
+        // the text alone can't tell the siblings from a cut element
+        let chunks = split("<p>a b</p><p>c d</p>", 10, &[])?;
+        assert_eq!(chunks, ["<p>a b</p>", "<p>c d</p>"]);
+        assert_eq!(join(&chunks), "<p>a bc d</p>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_groups() -> TestResult {
+        for html in [
+            "<p><b>bold <i>italic</i> words</b> and more text</p><b>x</b>",
+            "<p>a b</p><p>c d</p>",
+            "<p><b>bold text here</b> and <i>italic text here</i></p><p>x</p>",
+            "<b>one two</b><b>three four</b> and <b><b>five</b></b><b>six</b>",
+        ] {
+            for chunk_size in 8..=html.len() {
+                let Ok(chunks) = split_groups(html, chunk_size, &[]) else {
+                    continue;
+                };
+                assert_eq!(join_groups(&chunks), html, "{chunk_size}");
+            }
+        }
+
+        let chunks = split_groups("<p>a b</p><p>c d</p>", 10, &[])?;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(join_groups(&chunks), "<p>a b</p><p>c d</p>");
+
+        for chunk_size in (60..4096).step_by(7) {
+            let Ok(chunks) = split_groups(LONG_HTML, chunk_size, &[]) else {
+                continue;
+            };
+            assert_eq!(join_groups(&chunks), LONG_HTML, "{chunk_size}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_plain() {
+        let text = strip_tags(LONG_HTML);
+        for chunk_size in (4..1000).step_by(11) {
+            let chunks = split_plain(LONG_HTML, chunk_size);
+            assert!(chunks.iter().all(|chunk| chunk.len() <= chunk_size));
+            assert_eq!(chunks.concat(), text);
+        }
+
+        assert_eq!(
+            split_plain("<b>bold</b> and <i>italic</i>", 9),
+            vec!["bold and ", "italic"]
+        );
+        assert_eq!(split_plain("a👍", 2), vec!["a", "👍"]);
+    }
+
+    #[test]
+    fn test_split_comments_round_trip() -> TestResult {
+        let html = "<b>bold</b><!-- a > b --> text <i>italic</i><!-- <b> -->";
+        let result = split(html, 20, &[])?;
+        assert!(result.iter().any(|chunk| chunk.contains("<!-- a > b -->")));
+        assert_eq!(result.join(""), html);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_declarations() -> TestResult {
+        let html = r#"<?xml version="1.0"?><!DOCTYPE html><p>some text</p> and more"#;
+        let result = split(html, 24, &[])?;
+        assert_eq!(
+            result,
+            vec![
+                r#"<?xml version="1.0"?>"#,
+                "<!DOCTYPE html>",
+                "<p>some text</p>",
+                " and more"
+            ]
+        );
+        assert_eq!(result.concat(), html);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_stray_open_bracket() -> TestResult {
+        for text in ["a < b", "trailing <", "<unterminated", "<b>price</b> < 5"] {
+            for max_chunk_size in 8..16 {
+                let result = split(text, max_chunk_size, &[])?;
+                assert_eq!(clean(result.join("")), clean(text));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_utf16() -> TestResult {
+        let text = "👍👍👍 👍👍 👍👍👍👍 👍 👍👍👍👍👍👍👍 👍👍";
+        let max_chunk_size = 10;
+
+        let result = split_with_length_mode(text, max_chunk_size, &[], LengthMode::Utf16)?;
+        for chunk in &result {
+            assert!(
+                chunk.encode_utf16().count() <= max_chunk_size,
+                "Chunk exceeds max_chunk_size: {}",
+                chunk
+            );
+        }
+        assert_eq!(result.join(""), text);
+
+        let html = format!("<b>{text}</b> <i>{text}</i>");
+        let result = split_with_length_mode(&html, 20, &[], LengthMode::Utf16)?;
+        for chunk in &result {
+            assert!(chunk.encode_utf16().count() <= 20, "{chunk}");
+        }
+        assert_eq!(clean(result.join("")), clean(&html));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_display_width() -> TestResult {
+        use unicode_width::UnicodeWidthStr;
+
+        let text = "東京は日本の首都です。 大阪は 食べ物で 有名です。 京都には古い寺が多い。";
+        let html = format!("<p>{text}</p><b>漢字 かな カナ</b>");
+        for max_chunk_size in [12, 20, 31, 50] {
+            let result =
+                split_with_length_mode(&html, max_chunk_size, &[], LengthMode::DisplayWidth)?;
+            for chunk in &result {
+                assert!(chunk.width() <= max_chunk_size, "{chunk}");
+            }
+            assert!(result.iter().any(|chunk| chunk.len() > max_chunk_size));
+            assert_eq!(clean(result.join("")), clean(&html));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_chars() -> TestResult {
+        let text = "Hello 世界, this is 日本語 mixed with ASCII テキスト and more 文字 here";
+        let max_chunk_size = 10;
+
+        let result = split_with_length_mode(text, max_chunk_size, &[], LengthMode::Chars)?;
+        for chunk in &result {
+            assert!(
+                chunk.chars().count() <= max_chunk_size,
+                "Chunk exceeds max_chunk_size: {}",
+                chunk
+            );
+        }
+        assert!(result.iter().any(|chunk| chunk.len() > max_chunk_size));
+        assert_eq!(result.join(""), text);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_iter_take() -> TestResult {
+        let chunks = split(LONG_HTML, 100, &[])?;
+        let taken = split_iter(LONG_HTML, 100, &[])
+            .take(2)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(taken, chunks[..2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_iter_matches_split() -> TestResult {
+        for chunk_size in 60..300 {
+            let chunks = match split(LONG_HTML, chunk_size, &["a"]) {
+                Ok(chunks) => chunks,
+                Err(SplitError::SplitExceededTheLimit(chunks)) => chunks,
+                err => err?,
+            };
+
+            let mut iterated = vec![];
+            for chunk in split_iter(LONG_HTML, chunk_size, &["a"]) {
+                match chunk {
+                    Ok(chunk) => iterated.push(chunk),
+                    Err(SplitError::SubdividedExceedingTheLimit(tgs)) => {
+                        iterated.extend(tgs.iter().map(TokenGroup::to_string))
+                    }
+                    Err(err) => Err(err)?,
+                }
+            }
+
+            assert_eq!(iterated, chunks);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_with_ranges() -> TestResult {
+        for max_chunk_size in (60..600).step_by(13) {
+            let chunks = split_with_ranges(LONG_HTML, max_chunk_size, &[])?;
+            let (texts, ranges): (Vec<_>, Vec<_>) = chunks.into_iter().unzip();
+            assert_eq!(texts, split(LONG_HTML, max_chunk_size, &[])?);
+
+            assert_eq!(ranges.first().map(|range| range.start), Some(0));
+            assert_eq!(ranges.last().map(|range| range.end), Some(LONG_HTML.len()));
+            for pair in ranges.windows(2) {
+                assert!(pair[0].start <= pair[0].end, "{pair:?}");
+                assert_eq!(pair[0].end, pair[1].start);
+            }
+            for (text, range) in texts.iter().zip(ranges) {
+                assert_eq!(clean(text), clean(&LONG_HTML[range]), "{max_chunk_size}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_with_ranges_reopened_tags() -> TestResult {
+        let html = "<b>bold words here</b> plain";
+        let chunks = split_with_ranges(html, 16, &[])?;
+        assert_eq!(
+            chunks,
+            vec![
+                ("<b>bold </b>".to_string(), 0..8),
+                ("<b>words </b>".to_string(), 8..14),
+                ("<b>here</b>".to_string(), 14..22),
+                (" plain".to_string(), 22..28),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_no_empty_chunks() -> TestResult {
+        // the no_split tag doesn't fit after the reopened `<p>`, so it's moved to a new chunk
+        let html = "<p><a href='x'>link text</a> and some more text</p>";
+        let err = split(html, 30, &["a"]).unwrap_err();
+        let chunks = err.recovered_chunks().unwrap();
+        assert!(chunks.iter().all(|chunk| !chunk.is_empty()), "{chunks:?}");
+        assert_eq!(clean(chunks.concat()), clean(html));
+
+        for chunk_size in 60..400 {
+            let chunks = match split(LONG_HTML, chunk_size, &["a"]) {
+                Ok(chunks) => chunks,
+                Err(SplitError::SplitExceededTheLimit(chunks)) => chunks,
+                err => err?,
+            };
+            assert!(chunks.iter().all(|chunk| !chunk.is_empty()), "{chunks:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_tags() {
+        let text = strip_tags(LONG_HTML);
+        assert!(!text.contains(['<', '>']));
+        assert!(text.contains("inline URL"));
+        assert!(text.contains("pre-formatted fixed-width code block"));
+    }
+
+    #[test]
+    fn test_split_visible_text() -> TestResult {
+        let html = "<b>a</b><i>b</i><u>c</u><s>d</s> <b><i>bold italic</i> text</b>";
+        let result = split_with_length_mode(html, 12, &[], LengthMode::VisibleText)?;
+        assert_eq!(
+            result,
+            vec![
+                "<b>a</b><i>b</i><u>c</u><s>d</s> ",
+                "<b><i>bold italic</i> </b>",
+                "<b>text</b>"
+            ]
+        );
+        for chunk in &result {
+            assert!(LengthMode::VisibleText.measure(chunk) <= 12, "{chunk}");
+        }
+        assert_eq!(clean(result.concat()), clean(html));
+
+        Ok(())
+    }
 }
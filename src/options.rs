@@ -0,0 +1,2552 @@
+use crate::error::{OwnedSplitError, SplitError};
+use crate::ext::is_breaking_space;
+use crate::length_mode::LengthMode;
+use crate::selector::Selector;
+use crate::split_iter::{SplitInput, SplitIter};
+use crate::token::{Token, DEFAULT_VOID_TAGS};
+use crate::token_group::{RootTokenGroups, TokenGroup, PREFORMATTED_TAGS};
+use crate::tokenizer::{Tokenizer, RAW_TEXT_TAGS};
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Telegram's message length limit
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 4096;
+
+/// Returns the prefix and the suffix for the chunk with the given index out of the total count
+pub type ChunkDecorator = Arc<dyn Fn(usize, usize) -> (String, String) + Send + Sync>;
+
+/// Tags kept whole by default, see [`SplitOptions::atomic_tags`]
+pub const DEFAULT_ATOMIC_TAGS: &[&str] = &["pre", "code", "li"];
+
+/// Tells whether a token matches some condition, e.g. whether a tag must not be split
+pub type TokenPredicate = Arc<dyn Fn(&Token) -> bool + Send + Sync>;
+
+/// Gives a replacement for an open tag reopened in a continuation chunk, see
+/// [`SplitOptions::reopen_with`]
+pub type ReopenTag = Arc<dyn Fn(&Token) -> Option<&'static str> + Send + Sync>;
+
+/// Tells whether the text may be cut right after a character, see [`SplitOptions::break_chars`]
+pub type BreakPredicate = Arc<dyn Fn(char) -> bool + Send + Sync>;
+
+/// Reopens the common formatting tags without attributes, e.g. `<a>` for `<a href="...">`
+pub fn bare_tag(token: &Token) -> Option<&'static str> {
+    const BARE_TAGS: &[&str] = &[
+        "<a>",
+        "<b>",
+        "<blockquote>",
+        "<code>",
+        "<del>",
+        "<div>",
+        "<em>",
+        "<i>",
+        "<ins>",
+        "<li>",
+        "<ol>",
+        "<p>",
+        "<pre>",
+        "<s>",
+        "<span>",
+        "<strike>",
+        "<strong>",
+        "<u>",
+        "<ul>",
+    ];
+
+    let tag_name = token.tag_name();
+    BARE_TAGS
+        .iter()
+        .find(|tag| tag[1..tag.len() - 1].eq_ignore_ascii_case(tag_name))
+        .copied()
+}
+
+/// How the text is distributed among the chunks
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Distribution {
+    /// Fill every chunk as much as possible, which may leave a short one at the end
+    #[default]
+    Greedy,
+    /// Same as [`Distribution::Greedy`]: the first chunks are full and the last one takes the
+    /// slack, the opposite of [`Distribution::FillLast`]
+    FillFirst,
+    /// Same as [`Distribution::Greedy`], but the root-level elements are packed from the end, so
+    /// the last chunks are full and the first one is left short. An element subdivided on its own
+    /// is still cut starting with its beginning.
+    FillLast,
+    /// Make the chunks roughly equal: the longest one is kept as short as possible without
+    /// producing more chunks than [`Distribution::Greedy`] does. Takes a few more passes over the
+    /// text to find the limit to split with.
+    Balanced,
+}
+
+/// What to do with a word that does not fit into the rest of a chunk
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum WordBreak {
+    /// Split at the last whitespace that fits, or cut the word if there's no such whitespace
+    #[default]
+    AsLastResort,
+    /// Move the whole word to the next chunk; a word that doesn't fit into an empty chunk is a
+    /// [`SplitError::SubdivisionImpossible`]. Useful for URLs and code identifiers.
+    Never,
+    /// Same as [`WordBreak::AsLastResort`], but a word is only cut between grapheme clusters, so
+    /// a ZWJ emoji or a letter with combining marks stays whole
+    AtGraphemes,
+}
+
+/// The numbers about the chunks of a split, see [`SplitOptions::split_with_stats`]. The lengths
+/// are measured in the `length_mode` units.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SplitStats {
+    pub count: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub avg_len: f64,
+    /// The number of tags reopened at the start of a chunk because the previous one was split
+    /// inside them; each also got closed at the end of the previous chunk
+    pub reopened_tag_count: usize,
+}
+
+/// What a chunk of a split is made of, see [`SplitOptions::split_with_metadata`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChunkMetadata {
+    /// The byte range of the text the chunk was cut from, see [`SplitOptions::split_with_ranges`]
+    pub range: Range<usize>,
+    /// The byte ranges of the `no_split` elements the chunk contains whole, in order
+    pub no_split_blocks: Vec<Range<usize>>,
+}
+
+/// The steps [`SplitOptions::normalize`] applies to the input, all off by default. The chunks and
+/// the errors of a split borrow its input, so the input is normalized as a step of its own before
+/// splitting rather than by the splitter.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct Normalization {
+    pub(crate) strip_bom: bool,
+    pub(crate) normalize_newlines: bool,
+    pub(crate) collapse_whitespace: bool,
+}
+
+impl Normalization {
+    /// Drop the byte order mark at the start of the input
+    pub fn strip_bom(mut self, strip_bom: bool) -> Self {
+        self.strip_bom = strip_bom;
+        self
+    }
+
+    /// Convert `\r\n` and `\r` line endings to `\n`
+    pub fn normalize_newlines(mut self, normalize_newlines: bool) -> Self {
+        self.normalize_newlines = normalize_newlines;
+        self
+    }
+
+    /// Fold the runs of ASCII whitespace in the text into a single space, like a browser does.
+    /// The text of `<pre>` and `<code>` is left as is, and so is the raw text of `<script>` and
+    /// `<style>`.
+    pub fn collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+}
+
+/// Splitting configuration, see [`crate::split`] for the defaults.
+///
+/// ```
+/// use dumb_html_splitter::options::SplitOptions;
+///
+/// let chunks = SplitOptions::default()
+///     .max_chunk_size(24)
+///     .no_split(&["a"])
+///     .split("<b>bold</b> and <a href='x'>link</a>")
+///     .unwrap();
+/// assert_eq!(chunks, vec!["<b>bold</b> and ", "<a href='x'>link</a>"]);
+/// ```
+#[derive(Clone)]
+pub struct SplitOptions {
+    pub(crate) max_chunk_size: usize,
+    pub(crate) tolerance: usize,
+    pub(crate) no_split: Vec<Selector>,
+    pub(crate) no_split_if: Option<TokenPredicate>,
+    pub(crate) atomic_tags: Vec<Selector>,
+    pub(crate) hard_split_tags: Vec<Selector>,
+    pub(crate) break_after: Vec<Selector>,
+    pub(crate) void_tags: Arc<[String]>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_tags_per_chunk: Option<usize>,
+    pub(crate) keep_empty_tags: bool,
+    pub(crate) strict: bool,
+    pub(crate) verify: bool,
+    pub(crate) normalize_self_closing: bool,
+    pub(crate) soft_break_after: Vec<Selector>,
+    pub(crate) case_sensitive_tags: bool,
+    pub(crate) match_local_names: bool,
+    pub(crate) length_mode: LengthMode,
+    pub(crate) overlap: usize,
+    pub(crate) word_break: WordBreak,
+    pub(crate) distribution: Distribution,
+    pub(crate) sentence_aware: bool,
+    pub(crate) protect_urls: bool,
+    pub(crate) cut_marker: Option<&'static str>,
+    pub(crate) break_chars: Option<BreakPredicate>,
+    pub(crate) continuation_markers: Option<(&'static str, &'static str)>,
+    #[cfg(feature = "regex")]
+    pub(crate) atomic_pattern: Option<regex::Regex>,
+    pub(crate) min_chunk_size: usize,
+    pub(crate) hard_max_chunk_size: Option<usize>,
+    pub(crate) decorator: Option<ChunkDecorator>,
+    pub(crate) reopen: Option<ReopenTag>,
+    pub(crate) reopen_tags: bool,
+    pub(crate) trim_chunks: bool,
+}
+
+impl Debug for SplitOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("SplitOptions");
+        debug
+            .field("max_chunk_size", &self.max_chunk_size)
+            .field("tolerance", &self.tolerance)
+            .field("no_split", &self.no_split)
+            .field("no_split_if", &self.no_split_if.as_ref().map(|_| ".."))
+            .field("atomic_tags", &self.atomic_tags)
+            .field("hard_split_tags", &self.hard_split_tags)
+            .field("break_after", &self.break_after)
+            .field("void_tags", &self.void_tags)
+            .field("max_depth", &self.max_depth)
+            .field("max_tags_per_chunk", &self.max_tags_per_chunk)
+            .field("keep_empty_tags", &self.keep_empty_tags)
+            .field("strict", &self.strict)
+            .field("verify", &self.verify)
+            .field("normalize_self_closing", &self.normalize_self_closing)
+            .field("soft_break_after", &self.soft_break_after)
+            .field("case_sensitive_tags", &self.case_sensitive_tags)
+            .field("match_local_names", &self.match_local_names)
+            .field("length_mode", &self.length_mode)
+            .field("overlap", &self.overlap)
+            .field("word_break", &self.word_break)
+            .field("distribution", &self.distribution)
+            .field("sentence_aware", &self.sentence_aware)
+            .field("protect_urls", &self.protect_urls)
+            .field("cut_marker", &self.cut_marker)
+            .field("break_chars", &self.break_chars.as_ref().map(|_| ".."))
+            .field("continuation_markers", &self.continuation_markers)
+            .field("min_chunk_size", &self.min_chunk_size)
+            .field("hard_max_chunk_size", &self.hard_max_chunk_size)
+            .field("decorator", &self.decorator.as_ref().map(|_| ".."))
+            .field("reopen", &self.reopen.as_ref().map(|_| ".."))
+            .field("reopen_tags", &self.reopen_tags)
+            .field("trim_chunks", &self.trim_chunks);
+        #[cfg(feature = "regex")]
+        debug.field("atomic_pattern", &self.atomic_pattern);
+        debug.finish()
+    }
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            tolerance: 0,
+            no_split: vec![],
+            no_split_if: None,
+            atomic_tags: DEFAULT_ATOMIC_TAGS
+                .iter()
+                .map(|tag| Selector::parse(tag))
+                .collect(),
+            hard_split_tags: vec![],
+            break_after: vec![],
+            void_tags: DEFAULT_VOID_TAGS
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+            max_depth: None,
+            max_tags_per_chunk: None,
+            keep_empty_tags: false,
+            strict: false,
+            verify: false,
+            normalize_self_closing: false,
+            soft_break_after: vec![],
+            case_sensitive_tags: false,
+            match_local_names: false,
+            length_mode: LengthMode::default(),
+            overlap: 0,
+            word_break: WordBreak::default(),
+            distribution: Distribution::default(),
+            sentence_aware: false,
+            protect_urls: false,
+            cut_marker: None,
+            break_chars: None,
+            continuation_markers: None,
+            #[cfg(feature = "regex")]
+            atomic_pattern: None,
+            min_chunk_size: 0,
+            hard_max_chunk_size: None,
+            decorator: None,
+            reopen: None,
+            reopen_tags: true,
+            trim_chunks: false,
+        }
+    }
+}
+
+impl SplitOptions {
+    pub fn max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    /// Let a `no_split` element that doesn't fit into a chunk exceed `max_chunk_size` by up to
+    /// `tolerance` instead of failing with [`SplitError::SplitExceededTheLimit`]. The text is
+    /// never split beyond `max_chunk_size`.
+    pub fn tolerance(mut self, tolerance: usize) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Tags that must not be split unless they don't fit into a chunk on their own. Besides plain
+    /// tag names, simple selectors are accepted: `span[class~=tg-spoiler]`, `code[class=x]`, or
+    /// `[data-atomic]` for any tag with the attribute. `*` keeps every element whole, so the text
+    /// is only split outside the tags.
+    pub fn no_split(self, no_split: &[&str]) -> Self {
+        self.no_split_tags(no_split)
+    }
+
+    /// Same as [`Self::no_split`], but takes any strings, like the `Vec<String>` of a config
+    pub fn no_split_tags<I, S>(mut self, no_split: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.no_split = no_split
+            .into_iter()
+            .map(|tag| Selector::parse(tag.as_ref()))
+            .collect();
+        self
+    }
+
+    /// Same as [`Self::no_split`], but the open tags are matched by a predicate, like the links
+    /// with an external `href` only. A tag is kept whole if either matches.
+    pub fn no_split_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&Token) -> bool + Send + Sync + 'static,
+    {
+        self.no_split_if = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Tags kept whole as long as they fit into a chunk on their own, [`DEFAULT_ATOMIC_TAGS`] by
+    /// default. Unlike [`Self::no_split`] ones, they're split as usual when they don't. Accepts
+    /// the same selectors, pass an empty slice to disable.
+    pub fn atomic_tags(mut self, atomic_tags: &[&str]) -> Self {
+        self.atomic_tags = atomic_tags.iter().map(|tag| Selector::parse(tag)).collect();
+        self
+    }
+
+    /// Root-level elements that end a chunk, even if more would fit, like `p` or `div` to keep
+    /// every paragraph in its own chunk. Accepts the same selectors as [`Self::no_split`].
+    pub fn hard_split_tags(mut self, hard_split_tags: &[&str]) -> Self {
+        self.hard_split_tags = hard_split_tags
+            .iter()
+            .map(|tag| Selector::parse(tag))
+            .collect();
+        self
+    }
+
+    /// Void elements that end a chunk, even if more would fit, like `hr`. Accepts the same
+    /// selectors as [`Self::no_split`].
+    pub fn break_after(mut self, break_after: &[&str]) -> Self {
+        self.break_after = break_after.iter().map(|tag| Selector::parse(tag)).collect();
+        self
+    }
+
+    /// Void elements to split after when the text that follows doesn't fit, instead of
+    /// splitting the text itself, like `br`
+    pub fn soft_break_after(mut self, soft_break_after: &[&str]) -> Self {
+        self.soft_break_after = soft_break_after
+            .iter()
+            .map(|tag| Selector::parse(tag))
+            .collect();
+        self
+    }
+
+    /// Fails with [`SplitError::MaxDepthExceeded`] when the tags are nested deeper than
+    /// `max_depth`, to protect against adversarial input
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Start a new chunk rather than let more than `max_tags_per_chunk` elements into one, like
+    /// Telegram does with the message entities. The tags reopened in a chunk count too, the void
+    /// and self-closing ones don't. Splitting fails with [`SplitError::InvalidOption`] for 0.
+    pub fn max_tags_per_chunk(mut self, max_tags_per_chunk: Option<usize>) -> Self {
+        self.max_tags_per_chunk = max_tags_per_chunk;
+        self
+    }
+
+    /// Keep the empty elements of the input, like `<a id="anchor"></a>`, instead of dropping them
+    pub fn keep_empty_tags(mut self, keep_empty_tags: bool) -> Self {
+        self.keep_empty_tags = keep_empty_tags;
+        self
+    }
+
+    /// Fail with [`SplitError::MalformedTag`] on an unterminated tag or comment, or a stray `<`,
+    /// instead of keeping it as text
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Re-tokenize every chunk of [`Self::split`] and fail with
+    /// [`SplitError::VerificationFailed`] if one is not balanced. A safety net against the bugs of
+    /// subdivision; it's skipped without [`Self::reopen_tags`], since the chunks are not balanced
+    /// then.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Render the self-closing tags like `<b/>` as `<b></b>`, and `<br/>` as `<br>`. The chunks
+    /// are still measured with the tags as they are in the input.
+    pub fn normalize_self_closing(mut self, normalize_self_closing: bool) -> Self {
+        self.normalize_self_closing = normalize_self_closing;
+        self
+    }
+
+    /// The elements that never have a close tag, [`DEFAULT_VOID_TAGS`] by default. A void tag
+    /// like `<br>` doesn't need a slash to be self-closing, while `<br>` with `br` removed from
+    /// the set expects a `</br>`. The names are compared ignoring the ASCII case.
+    pub fn void_tags(mut self, void_tags: &[&str]) -> Self {
+        self.void_tags = void_tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    /// Match `no_split` tag names exactly instead of ignoring the ASCII case
+    pub fn case_sensitive_tags(mut self, case_sensitive_tags: bool) -> Self {
+        self.case_sensitive_tags = case_sensitive_tags;
+        self
+    }
+
+    /// Let the tag names match without the namespace prefix too, so `rect` matches `<svg:rect>`
+    pub fn match_local_names(mut self, match_local_names: bool) -> Self {
+        self.match_local_names = match_local_names;
+        self
+    }
+
+    pub fn length_mode(mut self, length_mode: LengthMode) -> Self {
+        self.length_mode = length_mode;
+        self
+    }
+
+    /// Repeats up to `overlap` units of the previous chunk's tail at the start of the next one.
+    ///
+    /// The overlap starts at a word boundary, and the formatting tags open at that point are
+    /// reopened. The tags count toward `overlap` too, and the room for the overlap is reserved in
+    /// every chunk, so chunks still fit `max_chunk_size`.
+    pub fn overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn word_break(mut self, word_break: WordBreak) -> Self {
+        self.word_break = word_break;
+        self
+    }
+
+    pub fn distribution(mut self, distribution: Distribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Prefer ending a chunk after a sentence when the text has to be split. Falls back to
+    /// `word_break` if no sentence ends within the chunk.
+    pub fn sentence_aware(mut self, sentence_aware: bool) -> Self {
+        self.sentence_aware = sentence_aware;
+        self
+    }
+
+    /// Move a URL in the text, like `https://example.com/path`, to the next chunk as a whole
+    /// instead of cutting it. A URL is only cut if it doesn't fit into a chunk of its own.
+    pub fn protect_urls(mut self, protect_urls: bool) -> Self {
+        self.protect_urls = protect_urls;
+        self
+    }
+
+    /// Keep the matches of `pattern` in the text whole, like `\$[^$]+\$` for LaTeX spans or the
+    /// product codes. A match is only cut if it doesn't fit into a chunk of its own.
+    #[cfg(feature = "regex")]
+    pub fn atomic_pattern(mut self, pattern: regex::Regex) -> Self {
+        self.atomic_pattern = Some(pattern);
+        self
+    }
+
+    /// Appends `marker`, like `…`, to a word cut in the middle because it didn't fit into a chunk.
+    /// The room for the marker is reserved, so the chunks still fit `max_chunk_size`.
+    pub fn cut_marker(mut self, marker: &'static str) -> Self {
+        self.cut_marker = Some(marker).filter(|marker| !marker.is_empty());
+        self
+    }
+
+    /// Lets the text be cut next to the characters matching `predicate`, like the `/` of a long
+    /// path, besides the whitespace. The character itself is never cut off.
+    pub fn break_chars<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        self.break_chars = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Appends `end`, like ` ↵`, to a chunk cut inside an element, and prepends `start`, like
+    /// `↳ `, to the chunk the element continues in. The chunks cut where no tag is open are left
+    /// as is. The room for both markers is reserved in every cut, so the chunks still fit
+    /// `max_chunk_size`.
+    pub fn continuation_markers(mut self, end: &'static str, start: &'static str) -> Self {
+        self.continuation_markers =
+            Some((end, start)).filter(|(end, start)| !end.is_empty() || !start.is_empty());
+        self
+    }
+
+    /// The room reserved for [`Self::continuation_markers`] in every subdivided group
+    pub(crate) fn continuation_markers_len(&self) -> usize {
+        self.continuation_markers.map_or(0, |(end, start)| {
+            self.length_mode.measure(end) + self.length_mode.measure(start)
+        })
+    }
+
+    /// If the last chunk is shorter than `min_chunk_size`, it's merged into the previous one when
+    /// both fit `max_chunk_size` together, otherwise the two are split again evenly.
+    pub fn min_chunk_size(mut self, min_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size;
+        self
+    }
+
+    /// Treats `max_chunk_size` as a soft limit that a chunk may exceed up to
+    /// `hard_max_chunk_size` to avoid an ugly split: when a root-level element with an atomic or
+    /// `no_split` element inside doesn't fit `max_chunk_size` alone, or when cutting it would
+    /// leave a piece shorter than `min_chunk_size`. The last chunk is merged up to the hard limit
+    /// too, see [`Self::min_chunk_size`].
+    pub fn hard_max_chunk_size(mut self, hard_max_chunk_size: usize) -> Self {
+        self.hard_max_chunk_size = Some(hard_max_chunk_size);
+        self
+    }
+
+    /// The limit a chunk never exceeds, see [`Self::hard_max_chunk_size`]
+    pub(crate) fn hard_limit(&self) -> usize {
+        self.hard_max_chunk_size
+            .map_or(self.max_chunk_size, |hard| hard.max(self.max_chunk_size))
+    }
+
+    /// Reopens the tags continued from the previous chunk in the form returned by `reopen`, e.g.
+    /// [`bare_tag`], instead of repeating them with all the attributes. Saves room for the content
+    /// at the cost of fidelity: a reopened `<a>` is no longer a link.
+    pub fn reopen_with<F>(mut self, reopen: F) -> Self
+    where
+        F: Fn(&Token) -> Option<&'static str> + Send + Sync + 'static,
+    {
+        self.reopen = Some(Arc::new(reopen));
+        self
+    }
+
+    /// When set (the default), a tag split between chunks is closed at the end of one chunk and
+    /// reopened at the start of the next, so every chunk is valid HTML on its own.
+    ///
+    /// When unset, chunks are literal slices of the source: nothing is added to them, and
+    /// `chunks.concat()` gives back the source. The splitter still prefers to cut where no tag is
+    /// open, but when it has to cut inside an element, the chunks are left unbalanced and a
+    /// consumer rendering them one by one will see broken markup.
+    pub fn reopen_tags(mut self, reopen_tags: bool) -> Self {
+        self.reopen_tags = reopen_tags;
+        self
+    }
+
+    /// Trim the whitespace at the edges of the text of every chunk, like the one left around a
+    /// cut between words. The tags and the text of `<pre>`, `<code>`, `<script>` and `<style>` are
+    /// left as is. The chunks are measured before trimming, and the ones left empty are dropped.
+    pub fn trim_chunks(mut self, trim_chunks: bool) -> Self {
+        self.trim_chunks = trim_chunks;
+        self
+    }
+
+    /// Applies [`Self::trim_chunks`] to a rendered chunk; `None` if nothing is left of it. Every
+    /// chunk rendered goes through here, so that all the ways to split agree on the chunks.
+    pub(crate) fn finish_chunk(&self, chunk: String) -> Option<String> {
+        let chunk = if self.trim_chunks {
+            TokenGroup::from_string_literally(&chunk)
+                .trimmed(self)
+                .to_string()
+        } else {
+            chunk
+        };
+        (!chunk.is_empty()).then_some(chunk)
+    }
+
+    /// Same as [`Self::finish_chunk`], but for a chunk kept as a token group
+    pub(crate) fn finish_group<'a>(&self, tg: TokenGroup<'a>) -> Option<TokenGroup<'a>> {
+        let tg = if self.trim_chunks {
+            tg.trimmed(self)
+        } else {
+            tg
+        };
+        (!tg.tokens.is_empty()).then_some(tg)
+    }
+
+    /// Adds a prefix and a suffix to every chunk, e.g. `(1/5)`. The decorator receives the chunk
+    /// index and the total number of chunks.
+    ///
+    /// Since the total is known only after splitting, the text is split again with more room
+    /// reserved until all decorated chunks fit `max_chunk_size`. Applies to [`Self::split`] only.
+    pub fn decorate<F>(mut self, decorator: F) -> Self
+    where
+        F: Fn(usize, usize) -> (String, String) + Send + Sync + 'static,
+    {
+        self.decorator = Some(Arc::new(decorator));
+        self
+    }
+
+    /// Applies the `normalization` steps to `text`, to split the result with these options; only
+    /// allocates if anything changes
+    pub fn normalize<'a>(&self, text: &'a str, normalization: Normalization) -> Cow<'a, str> {
+        let text = match text.strip_prefix('\u{feff}') {
+            Some(rest) if normalization.strip_bom => rest,
+            _ => text,
+        };
+        let mut text = if normalization.normalize_newlines && text.contains('\r') {
+            Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+        } else {
+            Cow::Borrowed(text)
+        };
+        if normalization.collapse_whitespace {
+            if let Some(collapsed) = collapse_whitespace(&text, self) {
+                text = Cow::Owned(collapsed);
+            }
+        }
+        text
+    }
+
+    /// Same as [`Self::split`], but the input is normalized first, see [`Self::normalize`]. The
+    /// chunks and the lengths are those of the normalized text, and the errors are owned since
+    /// they can't borrow it.
+    pub fn split_normalized(
+        &self,
+        text: &str,
+        normalization: Normalization,
+    ) -> Result<Vec<String>, OwnedSplitError> {
+        self.split(&self.normalize(text, normalization))
+            .map_err(SplitError::into_owned)
+    }
+
+    pub fn split<'a>(&self, text: &'a str) -> Result<Vec<String>, SplitError<'a>> {
+        self.split_from(SplitInput::Text(text))
+    }
+
+    /// Same as [`Self::split`], but for the root-level groups of a document tokenized already, see
+    /// [`crate::prepare_token_groups`]. The options applied while tokenizing, like
+    /// [`Self::strict`] or [`Self::keep_empty_tags`], are those the groups were prepared with.
+    pub fn split_prepared<'a>(
+        &self,
+        groups: &[TokenGroup<'a>],
+    ) -> Result<Vec<String>, SplitError<'a>> {
+        self.split_from(SplitInput::Groups(groups))
+    }
+
+    fn split_from<'a>(&self, input: SplitInput<'a, '_>) -> Result<Vec<String>, SplitError<'a>> {
+        let Some(decorator) = &self.decorator else {
+            return self.split_undecorated_from(input);
+        };
+
+        let mut reserved_len = 0;
+        loop {
+            if reserved_len >= self.max_chunk_size {
+                return Err(SplitError::InvalidLen(reserved_len));
+            }
+
+            let mut options = self
+                .clone()
+                .max_chunk_size(self.max_chunk_size - reserved_len);
+            options.hard_max_chunk_size = self
+                .hard_max_chunk_size
+                .map(|hard| hard.saturating_sub(reserved_len));
+            let chunks = options.split_undecorated_from(input)?;
+
+            let total = chunks.len();
+            let decorations = (0..total)
+                .map(|index| decorator(index, total))
+                .collect::<Vec<_>>();
+            let required_len = decorations
+                .iter()
+                .map(|(prefix, suffix)| {
+                    self.length_mode.measure(prefix) + self.length_mode.measure(suffix)
+                })
+                .max()
+                .unwrap_or_default();
+
+            if required_len <= reserved_len {
+                return Ok(chunks
+                    .into_iter()
+                    .zip(decorations)
+                    .map(|(chunk, (prefix, suffix))| prefix + &chunk + &suffix)
+                    .collect());
+            }
+
+            reserved_len = required_len;
+        }
+    }
+
+    pub(crate) fn split_undecorated<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<Vec<String>, SplitError<'a>> {
+        self.split_undecorated_from(SplitInput::Text(text))
+    }
+
+    fn split_undecorated_from<'a>(
+        &self,
+        input: SplitInput<'a, '_>,
+    ) -> Result<Vec<String>, SplitError<'a>> {
+        let mut chunks = vec![];
+        let mut has_exceeded = false;
+
+        for chunk in self.split_iter_from(input) {
+            match chunk {
+                Ok(chunk) => chunks.push(chunk),
+                Err(SplitError::SubdividedExceedingTheLimit(tgs)) => {
+                    has_exceeded = true;
+                    chunks.extend(
+                        tgs.iter()
+                            .filter_map(|tg| self.finish_chunk(tg.to_string())),
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if self.verify && self.reopen_tags {
+            let unbalanced = chunks
+                .iter()
+                .position(|chunk| crate::validate_balanced(chunk).is_err());
+            if let Some(chunk_index) = unbalanced {
+                return Err(SplitError::VerificationFailed { chunk_index });
+            }
+        }
+
+        if has_exceeded {
+            return Err(SplitError::SplitExceededTheLimit(chunks));
+        }
+
+        Ok(chunks)
+    }
+
+    /// The smallest `max_chunk_size` [`Self::split`] succeeds with for `text`, whatever
+    /// `max_chunk_size` is set to: the longest piece that can't be cut along with the tags
+    /// reopened around it
+    pub fn minimum_chunk_size<'a>(&self, text: &'a str) -> Result<usize, SplitError<'a>> {
+        let mut min_chunk_size = 1;
+        let tgs = RootTokenGroups::new(text, self.length_mode)
+            .max_depth(self.max_depth)
+            .keep_empty_tags(self.keep_empty_tags)
+            .void_tags(self.void_tags.clone())
+            .strict(self.strict);
+        for tg in tgs {
+            min_chunk_size = min_chunk_size.max(tg?.min_chunk_size(self)?);
+        }
+        Ok(min_chunk_size)
+    }
+
+    /// Whether [`Self::split`] succeeds for `text` without any chunk exceeding the limit. Stops at
+    /// the first group that doesn't fit, and doesn't render the chunks unless there's a
+    /// decorator. The errors that are not about the limit are returned as is.
+    pub fn can_split<'a>(&self, text: &'a str) -> Result<bool, SplitError<'a>> {
+        let result = if self.decorator.is_some() {
+            self.split(text).map(|_| ())
+        } else {
+            let mut iter = self.split_iter(text);
+            std::iter::from_fn(|| iter.next_group()).try_for_each(|tg| tg.map(|_| ()))
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(
+                SplitError::SplitExceededTheLimit(_)
+                | SplitError::SubdividedExceedingTheLimit(_)
+                | SplitError::SubdivisionImpossible { .. }
+                | SplitError::SubdivisionImpossibleUnicode(_),
+            ) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Same as [`Self::split`], but the chunks are kept as token groups. The overlap and the
+    /// decorator are string-level and are not applied.
+    pub fn split_groups<'a>(&self, text: &'a str) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+        let mut iter = self.split_iter(text);
+        let mut tgs = vec![];
+        let mut has_exceeded = false;
+
+        while let Some(tg) = iter.next_group() {
+            match tg {
+                Ok(tg) => tgs.extend(self.finish_group(tg)),
+                Err(SplitError::SubdividedExceedingTheLimit(exceeding)) => {
+                    has_exceeded = true;
+                    tgs.extend(exceeding.into_iter().filter_map(|tg| self.finish_group(tg)));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if has_exceeded {
+            return Err(SplitError::SplitExceededTheLimit(
+                tgs.iter().map(TokenGroup::to_string).collect(),
+            ));
+        }
+
+        Ok(tgs)
+    }
+
+    /// The number of chunks [`Self::split`] would produce, without rendering them. The chunks are
+    /// only rendered to report [`SplitError::SplitExceededTheLimit`], when there's a decorator, or
+    /// to tell the ones trimmed away with [`Self::trim_chunks`].
+    pub fn count_chunks<'a>(&self, text: &'a str) -> Result<usize, SplitError<'a>> {
+        self.count_chunks_from(SplitInput::Text(text))
+    }
+
+    fn count_chunks_from<'a>(&self, input: SplitInput<'a, '_>) -> Result<usize, SplitError<'a>> {
+        if self.decorator.is_some() {
+            return Ok(self.split_from(input)?.len());
+        }
+
+        let mut iter = self.split_iter_from(input);
+        let mut count = 0;
+        while let Some(tg) = iter.next_group() {
+            match tg {
+                Ok(tg) if self.trim_chunks => count += usize::from(iter.render(&tg).is_some()),
+                Ok(_) => count += 1,
+                Err(SplitError::SubdividedExceedingTheLimit(_)) => {
+                    return self
+                        .split_undecorated_from(input)
+                        .map(|chunks| chunks.len());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Same as [`Self::split`], but a chunk that is a single slice of `text` is borrowed from it;
+    /// only the chunks with the tags reopened or closed in them are allocated. With an overlap, a
+    /// decorator or [`Self::trim_chunks`], all the chunks are owned.
+    pub fn split_cow<'a>(&self, text: &'a str) -> Result<Vec<Cow<'a, str>>, SplitError<'a>> {
+        if self.decorator.is_some() || self.overlap != 0 || self.trim_chunks {
+            return Ok(self.split(text)?.into_iter().map(Cow::Owned).collect());
+        }
+
+        let mut iter = self.split_iter(text);
+        let mut chunks = vec![];
+        while let Some(tg) = iter.next_group() {
+            match tg {
+                Ok(tg) => chunks.push(match tg.as_source_slice(text) {
+                    Some(chunk) => Cow::Borrowed(chunk),
+                    None => Cow::Owned(tg.to_string()),
+                }),
+                Err(SplitError::SubdividedExceedingTheLimit(_)) => {
+                    return self
+                        .split_undecorated(text)
+                        .map(|chunks| chunks.into_iter().map(Cow::Owned).collect());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    /// Same as [`Self::split`], but every chunk comes with the byte range of `text` it was cut
+    /// from. The ranges are ascending and cover the whole `text`: the tags reopened in a chunk and
+    /// the overlap don't count, and the markup dropped in between (like empty tags) goes to the
+    /// preceding chunk. The decorator is not applied.
+    pub fn split_with_ranges<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<Vec<(String, Range<usize>)>, SplitError<'a>> {
+        let (chunks, tgs): (Vec<_>, Vec<_>) = self.split_rendered(text)?.into_iter().unzip();
+
+        let mut starts = vec![0];
+        for (index, tg) in tgs.iter().enumerate().skip(1) {
+            let start = tg
+                .source_range(tgs.get(index - 1), tgs.get(index + 1))
+                .map_or(starts[index - 1], |range| range.start);
+            starts.push(start.max(starts[index - 1]));
+        }
+        let ends = starts.iter().skip(1).copied().chain([text.len()]);
+
+        Ok(chunks
+            .into_iter()
+            .zip(starts.iter().copied().zip(ends))
+            .map(|(chunk, (start, end))| (chunk, start..end))
+            .collect())
+    }
+
+    /// Same as [`Self::split_with_ranges`], but every chunk comes with the `no_split` elements it
+    /// contains whole too, e.g. to check that every code block ended up in a single chunk. A
+    /// nested `no_split` element is listed along with the one around it.
+    pub fn split_with_metadata<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<Vec<(String, ChunkMetadata)>, SplitError<'a>> {
+        let chunks = self.split_with_ranges(text)?;
+
+        let mut blocks = vec![];
+        let mut stack = vec![];
+        for token in Tokenizer::new(text) {
+            if token.is_open_in(&self.void_tags) {
+                stack.push(token);
+            } else if token.is_close() {
+                let open = stack.pop().filter(|open| token.closes(open));
+                if let Some(open) = open.filter(|open| self.is_no_split(open)) {
+                    blocks.push(open.index()..token.index() + token.len());
+                }
+            }
+        }
+        blocks.sort_by_key(|block| block.start);
+
+        Ok(chunks
+            .into_iter()
+            .map(|(chunk, range)| {
+                let no_split_blocks = blocks
+                    .iter()
+                    .filter(|block| range.start <= block.start && block.end <= range.end)
+                    .cloned()
+                    .collect();
+                (
+                    chunk,
+                    ChunkMetadata {
+                        range,
+                        no_split_blocks,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Same as [`Self::split`], but every chunk comes with the names of the tags left open at its
+    /// end and reopened in the following chunk, outermost first. The decorator is not applied.
+    pub fn split_with_open_stacks<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<Vec<(String, Vec<String>)>, SplitError<'a>> {
+        let chunks = self.split_rendered(text)?;
+
+        let mut stacks = chunks
+            .windows(2)
+            .map(|pair| {
+                pair[0]
+                    .1
+                    .carried_stack(&pair[1].1)
+                    .iter()
+                    .map(|token| token.tag_name().to_string())
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        stacks.extend(chunks.first().map(|_| vec![]));
+
+        Ok(chunks
+            .into_iter()
+            .map(|(chunk, _)| chunk)
+            .zip(stacks)
+            .collect())
+    }
+
+    /// Same as [`Self::split`], but every chunk is passed through `f` along with its index. The
+    /// first error of `f` is returned as is, and the rest of the chunks are not processed.
+    pub fn split_map<'a, F, E>(&self, text: &'a str, mut f: F) -> Result<Vec<String>, E>
+    where
+        F: FnMut(usize, String) -> Result<String, E>,
+        E: From<SplitError<'a>>,
+    {
+        self.split(text)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| f(index, chunk))
+            .collect()
+    }
+
+    /// Writes the chunks [`Self::split`] would produce to `writer`, separated by `separator`, and
+    /// returns their number. The decorator is not applied.
+    ///
+    /// If some chunks exceed the limit, they are all written still, and the error is
+    /// [`SplitError::WrittenExceedingTheLimit`] with their number. Any other error stops the
+    /// writing, leaving the chunks written so far in `writer`; with [`Self::verify`], the
+    /// unbalanced chunk isn't written.
+    pub fn split_to_writer<'a, W: std::io::Write>(
+        &self,
+        text: &'a str,
+        separator: &[u8],
+        writer: &mut W,
+    ) -> Result<usize, SplitError<'a>> {
+        let mut count = 0;
+        let mut has_exceeded = false;
+        let mut write = |chunk: String| -> Result<(), SplitError<'a>> {
+            if self.verify && self.reopen_tags && crate::validate_balanced(&chunk).is_err() {
+                return Err(SplitError::VerificationFailed { chunk_index: count });
+            }
+            if count != 0 {
+                writer.write_all(separator)?;
+            }
+            writer.write_all(chunk.as_bytes())?;
+            count += 1;
+            Ok(())
+        };
+
+        for chunk in self.split_iter(text) {
+            match chunk {
+                Ok(chunk) => write(chunk)?,
+                Err(SplitError::SubdividedExceedingTheLimit(tgs)) => {
+                    has_exceeded = true;
+                    for chunk in tgs
+                        .iter()
+                        .filter_map(|tg| self.finish_chunk(tg.to_string()))
+                    {
+                        write(chunk)?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if has_exceeded {
+            return Err(SplitError::WrittenExceedingTheLimit(count));
+        }
+        Ok(count)
+    }
+
+    /// Splits every document of `docs`, in parallel with the `rayon` feature. The errors are
+    /// owned, so they can be sent across threads.
+    pub fn split_many(&self, docs: &[&str]) -> Vec<Result<Vec<String>, OwnedSplitError>> {
+        let split = |doc: &&str| self.split(doc).map_err(SplitError::into_owned);
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            docs.par_iter().map(split).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            docs.iter().map(split).collect()
+        }
+    }
+
+    /// Same as [`Self::split`], along with the numbers about the chunks to tune
+    /// `max_chunk_size` with. The decorator is not applied.
+    pub fn split_with_stats<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<(Vec<String>, SplitStats), SplitError<'a>> {
+        let (chunks, tgs): (Vec<_>, Vec<_>) = self.split_rendered(text)?.into_iter().unzip();
+
+        let lens = chunks
+            .iter()
+            .map(|chunk| self.length_mode.measure(chunk))
+            .collect::<Vec<_>>();
+        let stats = SplitStats {
+            count: chunks.len(),
+            min_len: lens.iter().copied().min().unwrap_or_default(),
+            max_len: lens.iter().copied().max().unwrap_or_default(),
+            avg_len: if lens.is_empty() {
+                0.0
+            } else {
+                lens.iter().sum::<usize>() as f64 / lens.len() as f64
+            },
+            reopened_tag_count: tgs
+                .windows(2)
+                .map(|pair| pair[0].carried_stack(&pair[1]).len())
+                .sum(),
+        };
+
+        Ok((chunks, stats))
+    }
+
+    /// The chunks with the overlap applied, along with the token groups they were rendered from
+    fn split_rendered<'a>(
+        &self,
+        text: &'a str,
+    ) -> Result<Vec<(String, TokenGroup<'a>)>, SplitError<'a>> {
+        let mut iter = self.split_iter(text);
+        let mut chunks = vec![];
+        let mut has_exceeded = false;
+
+        while let Some(tg) = iter.next_group() {
+            match tg {
+                Ok(tg) => {
+                    if let Some(chunk) = iter.render(&tg) {
+                        chunks.push((chunk, Some(tg)));
+                    }
+                }
+                Err(SplitError::SubdividedExceedingTheLimit(exceeding)) => {
+                    has_exceeded = true;
+                    chunks.extend(
+                        exceeding
+                            .iter()
+                            .filter_map(|tg| self.finish_chunk(tg.to_string()))
+                            .map(|chunk| (chunk, None)),
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if has_exceeded {
+            return Err(SplitError::SplitExceededTheLimit(
+                chunks.into_iter().map(|(chunk, _)| chunk).collect(),
+            ));
+        }
+
+        Ok(chunks
+            .into_iter()
+            .filter_map(|(chunk, tg)| Some((chunk, tg?)))
+            .collect())
+    }
+
+    pub fn split_iter<'a>(&self, text: &'a str) -> SplitIter<'a> {
+        self.split_iter_from(SplitInput::Text(text))
+    }
+
+    pub(crate) fn split_iter_from<'a>(&self, input: SplitInput<'a, '_>) -> SplitIter<'a> {
+        let options = match self.distribution {
+            Distribution::Greedy | Distribution::FillFirst | Distribution::FillLast => self.clone(),
+            Distribution::Balanced => {
+                let max_chunk_size = self.balanced_chunk_size(input);
+                self.clone().max_chunk_size(max_chunk_size)
+            }
+        };
+        SplitIter::new(input, options)
+    }
+
+    /// The smallest limit that splits the input into as many chunks as the greedy split does. The
+    /// chunks get close to the average length of the greedy ones this way.
+    fn balanced_chunk_size(&self, input: SplitInput) -> usize {
+        let greedy = self.clone().distribution(Distribution::Greedy);
+        let Ok(chunks) = greedy.split_undecorated_from(input) else {
+            // the iterator reports the error itself
+            return self.max_chunk_size;
+        };
+        if chunks.len() <= 1 {
+            return self.max_chunk_size;
+        }
+
+        let total_len = chunks
+            .iter()
+            .map(|chunk| self.length_mode.measure(chunk))
+            .sum::<usize>();
+        let mut low = total_len.div_ceil(chunks.len()).min(self.max_chunk_size);
+        let mut high = self.max_chunk_size;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let count = greedy.clone().max_chunk_size(mid).count_chunks_from(input);
+            if count.is_ok_and(|count| count <= chunks.len()) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        high
+    }
+
+    /// The byte ranges of `text` matched by the atomic pattern
+    #[cfg_attr(not(feature = "regex"), allow(unused_variables))]
+    pub(crate) fn atomic_runs(&self, text: &str) -> Vec<Range<usize>> {
+        #[cfg(feature = "regex")]
+        if let Some(pattern) = &self.atomic_pattern {
+            return pattern.find_iter(text).map(|run| run.range()).collect();
+        }
+        vec![]
+    }
+
+    fn matches_any(&self, selectors: &[Selector], token: &Token) -> bool {
+        selectors.iter().any(|selector| {
+            selector.matches(token, self.case_sensitive_tags, self.match_local_names)
+        })
+    }
+
+    pub(crate) fn is_atomic(&self, token: &Token) -> bool {
+        self.matches_any(&self.atomic_tags, token)
+    }
+
+    pub(crate) fn is_hard_split(&self, token: &Token) -> bool {
+        self.matches_any(&self.hard_split_tags, token)
+    }
+
+    pub(crate) fn is_break_after(&self, token: &Token) -> bool {
+        token.is_void_in(&self.void_tags) && self.matches_any(&self.break_after, token)
+    }
+
+    pub(crate) fn is_soft_break(&self, token: &Token) -> bool {
+        token.is_void_in(&self.void_tags) && self.matches_any(&self.soft_break_after, token)
+    }
+
+    pub(crate) fn is_break_char(&self, ch: char) -> bool {
+        is_breaking_space(ch)
+            || self
+                .break_chars
+                .as_ref()
+                .is_some_and(|predicate| predicate(ch))
+    }
+
+    /// Tells for every token of `tokens` whether it's text whose whitespace may be changed: not in
+    /// a preformatted element, nor in a raw text one like `<script>`
+    pub(crate) fn collapsible_text(&self, tokens: &[Token]) -> Vec<bool> {
+        let is_literal = |token: &Token| {
+            PREFORMATTED_TAGS
+                .iter()
+                .chain(RAW_TEXT_TAGS)
+                .any(|tag| tag.eq_ignore_ascii_case(token.tag_name()))
+        };
+
+        let mut literal_depth = 0usize;
+        tokens
+            .iter()
+            .map(|token| {
+                match token {
+                    Token::OpenTag(_, _)
+                        if token.is_open_in(&self.void_tags) && is_literal(token) =>
+                    {
+                        literal_depth += 1;
+                    }
+                    Token::CloseTag(_, _) if is_literal(token) => {
+                        literal_depth = literal_depth.saturating_sub(1);
+                    }
+                    _ => {}
+                }
+                matches!(token, Token::Text(..)) && literal_depth == 0
+            })
+            .collect()
+    }
+
+    pub(crate) fn is_no_split(&self, token: &Token) -> bool {
+        self.matches_any(&self.no_split, token)
+            || self
+                .no_split_if
+                .as_ref()
+                .is_some_and(|predicate| predicate(token))
+    }
+}
+
+/// `html` with the runs of ASCII whitespace in the text folded into a single space, except in the
+/// preformatted elements; `None` if there's nothing to fold
+fn collapse_whitespace(html: &str, options: &SplitOptions) -> Option<String> {
+    let tokens = Tokenizer::new(html).collect::<Vec<_>>();
+    let is_collapsible = options.collapsible_text(&tokens);
+
+    let mut collapsed = String::with_capacity(html.len());
+    for (token, is_collapsible) in tokens.iter().zip(is_collapsible) {
+        match token {
+            Token::Text(text, _) if is_collapsible => {
+                let mut is_after_space = false;
+                for ch in text.chars() {
+                    if !ch.is_ascii_whitespace() {
+                        collapsed.push(ch);
+                        is_after_space = false;
+                    } else if !is_after_space {
+                        collapsed.push(' ');
+                        is_after_space = true;
+                    }
+                }
+            }
+            _ => collapsed.push_str(token.as_text()),
+        }
+    }
+
+    (collapsed != html).then_some(collapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::LONG_HTML;
+    use crate::{clean, split};
+    use testresult::TestResult;
+
+    #[test]
+    fn test_options_match_split() -> TestResult {
+        for chunk_size in [100, 200, 4096] {
+            let chunks = split(LONG_HTML, chunk_size, &["a"])?;
+            let options = SplitOptions::default()
+                .max_chunk_size(chunk_size)
+                .no_split(&["a"]);
+            assert_eq!(options.split(LONG_HTML)?, chunks);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlap() -> TestResult {
+        let text = "one two three four five six seven eight nine ten eleven twelve";
+        let chunks = SplitOptions::default()
+            .max_chunk_size(24)
+            .overlap(10)
+            .split(text)?;
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert!(chunk.len() <= 24, "{chunk}");
+        }
+        // every chunk starts with a word-aligned tail of the previous one
+        for pair in chunks.windows(2) {
+            let previous = &pair[0];
+            assert!(
+                (1..previous.len())
+                    .filter(|&index| previous.as_bytes()[index - 1] == b' ')
+                    .any(|index| pair[1].starts_with(&previous[index..])),
+                "{pair:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlap_reopens_tags() -> TestResult {
+        let chunks = SplitOptions::default()
+            .max_chunk_size(40)
+            .overlap(16)
+            .split("<b>bold words here</b> plain words follow here")?;
+
+        assert_eq!(
+            chunks,
+            vec![
+                "<b>bold words here</b>",
+                "<b>here</b> plain words follow here"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlap_exceeding_max_chunk_size() {
+        let result = SplitOptions::default()
+            .max_chunk_size(10)
+            .overlap(10)
+            .split("text");
+        assert!(matches!(result, Err(SplitError::InvalidLen(10))));
+    }
+
+    #[test]
+    fn test_decorate() -> TestResult {
+        let max_chunk_size = 100;
+        let chunks = SplitOptions::default()
+            .max_chunk_size(max_chunk_size)
+            .decorate(|i, n| (format!("{}/{} ", i + 1, n), String::new()))
+            .split(LONG_HTML)?;
+
+        let total = chunks.len();
+        assert!(total >= 10, "the total must take two digits");
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.len() <= max_chunk_size, "{chunk}");
+            assert!(
+                chunk.starts_with(&format!("{}/{total} ", index + 1)),
+                "{chunk}"
+            );
+        }
+
+        let undecorated = chunks
+            .iter()
+            .map(|chunk| chunk.split_once(' ').unwrap().1)
+            .collect::<String>();
+        assert_eq!(clean(undecorated), clean(LONG_HTML));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_break_never() -> TestResult {
+        let word = "abcdefghijklmnopqrstuvwxyz0123456789ABCD";
+        let text = format!("some words before {word} and some after");
+
+        for max_chunk_size in 1..word.len() {
+            let result = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .word_break(WordBreak::Never)
+                .split(&text);
+            assert!(
+                matches!(result, Err(SplitError::SubdivisionImpossible { .. })),
+                "{max_chunk_size}: {result:?}"
+            );
+        }
+
+        for max_chunk_size in word.len()..word.len() + 20 {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .word_break(WordBreak::Never)
+                .split(&text)?;
+            assert!(
+                chunks.iter().any(|chunk| chunk.contains(word)),
+                "{chunks:?}"
+            );
+            assert_eq!(chunks.join(""), text);
+        }
+
+        let html = format!("<b>bold {word}</b> tail");
+        let chunks = SplitOptions::default()
+            .max_chunk_size(50)
+            .word_break(WordBreak::Never)
+            .split(&html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<b>bold </b>".to_string(),
+                format!("<b>{word}</b>"),
+                " tail".into()
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_aware() -> TestResult {
+        let text = "The first sentence. The second sentence is longer than the first.";
+        let options = SplitOptions::default().max_chunk_size(32);
+
+        let chunks = options.clone().split(text)?;
+        assert_eq!(chunks[0], "The first sentence. The second ");
+
+        let chunks = options.sentence_aware(true).split(text)?;
+        assert_eq!(chunks[0], "The first sentence. ");
+        assert_eq!(chunks.join(""), text);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_max_chunk_size() -> TestResult {
+        let chunks = SplitOptions::default().split(LONG_HTML)?;
+        assert_eq!(chunks, split(LONG_HTML, DEFAULT_MAX_CHUNK_SIZE, &[])?);
+        assert_eq!(chunks.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_chunk_size() -> TestResult {
+        let text = "one two three four five six";
+        let options = SplitOptions::default().max_chunk_size(24);
+
+        let chunks = options.clone().split(text)?;
+        assert_eq!(chunks, vec!["one two three four five ", "six"]);
+
+        let chunks = options.min_chunk_size(10).split(text)?;
+        assert_eq!(chunks, vec!["one two three ", "four five six"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_chunk_size_with_tags() -> TestResult {
+        let options = SplitOptions::default().max_chunk_size(16);
+
+        let text = "aaaa <b>bbbbbbb</b>c";
+        assert_eq!(
+            options.clone().split(text)?,
+            vec!["aaaa ", "<b>bbbbbbb</b>", "c"]
+        );
+        assert_eq!(
+            options.clone().min_chunk_size(4).split(text)?,
+            vec!["aaaa ", "<b>bbbbbbb</b>c"]
+        );
+
+        let text = "<b>aaaa bbbb cc</b>";
+        assert_eq!(
+            options.clone().max_chunk_size(18).split(text)?,
+            vec!["<b>aaaa bbbb </b>", "<b>cc</b>"]
+        );
+        assert_eq!(
+            options.max_chunk_size(18).min_chunk_size(10).split(text)?,
+            vec!["<b>aaaa </b>", "<b>bbbb cc</b>"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hard_max_chunk_size() -> TestResult {
+        let code = format!("<pre>{}a+1;</pre>", "let a = 1;\n".repeat(7));
+        assert_eq!(code.len(), 92);
+        let html = format!("<p>Some code:</p>{code}<p>That's it.</p>");
+
+        let soft = SplitOptions::default().max_chunk_size(80);
+        let chunks = soft.split(&html)?;
+        assert!(chunks.iter().all(|chunk| *chunk != code));
+
+        let options = soft.hard_max_chunk_size(100);
+        let chunks = options.split(&html)?;
+        assert_eq!(
+            chunks,
+            vec!["<p>Some code:</p>", &code, "<p>That's it.</p>"]
+        );
+        assert_eq!(chunks[1].len(), 92);
+
+        // too long even for the hard limit
+        let html = format!("{code}{code}");
+        let chunks = options.clone().split(&format!("<pre>{html}</pre>"))?;
+        assert!(chunks.len() > 2);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 80));
+
+        // the short remainder of a cut is avoided
+        let text = "one two three four five six";
+        let options = SplitOptions::default().max_chunk_size(24).min_chunk_size(5);
+        assert_eq!(
+            options.clone().split(text)?,
+            vec!["one two three ", "four five six"]
+        );
+        assert_eq!(options.hard_max_chunk_size(30).split(text)?, vec![text]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_chunk_size_keeps_content() -> TestResult {
+        for max_chunk_size in (60..300).step_by(7) {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .min_chunk_size(max_chunk_size / 3)
+                .split(LONG_HTML)?;
+
+            for chunk in &chunks {
+                assert!(chunk.len() <= max_chunk_size, "{chunk}");
+            }
+            assert_eq!(clean(chunks.concat()), clean(LONG_HTML));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_split_ignores_case() -> TestResult {
+        let html = "<p>some text <A href='http://example.com'>a long link text</A> and more</p>";
+        let options = SplitOptions::default().max_chunk_size(60);
+
+        for no_split in [["a"], ["A"]] {
+            let chunks = options.clone().no_split(&no_split).split(html)?;
+            assert!(
+                chunks
+                    .iter()
+                    .any(|chunk| chunk.contains(">a long link text</A>")),
+                "{chunks:?}"
+            );
+        }
+
+        let chunks = options
+            .case_sensitive_tags(true)
+            .no_split(&["a"])
+            .split(html)?;
+        assert!(
+            chunks
+                .iter()
+                .all(|chunk| !chunk.contains("a long link text")),
+            "{chunks:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_split_by_attribute() -> TestResult {
+        let spoiler = r#"<span class="tg-spoiler">a rather long spoiler</span>"#;
+        let plain = "<span>a rather long plain span that cannot fit as a whole</span>";
+        let html = format!("<p>{spoiler} {plain}</p>");
+        let options = SplitOptions::default().max_chunk_size(64);
+
+        let by_selector = options
+            .clone()
+            .no_split(&["span[class=tg-spoiler]"])
+            .split(&html)?;
+        let by_predicate = options
+            .no_split_if(|token| {
+                token
+                    .attributes()
+                    .any(|attribute| attribute == ("class", Some("tg-spoiler")))
+            })
+            .split(&html)?;
+
+        for chunks in [by_selector, by_predicate] {
+            assert!(
+                chunks.iter().any(|chunk| chunk.contains(spoiler)),
+                "{chunks:?}"
+            );
+            assert!(
+                chunks.iter().all(|chunk| !chunk.contains(plain)),
+                "{chunks:?}"
+            );
+            assert_eq!(clean(chunks.concat()), clean(&html));
+        }
+
+        // an empty entry, like the one of a `"a,,b"` list, keeps nothing whole
+        let html = "<p><b>aaaa bbbb cccc dddd</b></p>";
+        assert_eq!(
+            SplitOptions::default()
+                .max_chunk_size(20)
+                .no_split(&["", "code"])
+                .split(html)?,
+            SplitOptions::default().max_chunk_size(20).split(html)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_split_external_links() -> TestResult {
+        let external = r#"<a href="https://example.com">an external link</a>"#;
+        let local = r#"<a href="/about">a local link to the about page of the site</a>"#;
+        let html = format!("<p>See {external} and {local}</p>");
+
+        let chunks = SplitOptions::default()
+            .max_chunk_size(64)
+            .atomic_tags(&[])
+            .no_split_if(|token| token.tag_name() == "a" && token.as_text().contains("http"))
+            .split(&html)?;
+        assert!(
+            chunks.iter().any(|chunk| chunk.contains(external)),
+            "{chunks:?}"
+        );
+        assert!(
+            chunks.iter().all(|chunk| !chunk.contains(local)),
+            "{chunks:?}"
+        );
+        assert_eq!(clean(chunks.concat()), clean(&html));
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_tags() -> TestResult {
+        let sample = include_str!("./test_data/sample1.html");
+        let code_block = &sample[..sample.find("</pre>").unwrap() + "</pre>".len()];
+        let html = format!("<div>{}{sample}</div>", "Some introduction. ".repeat(10));
+
+        for max_chunk_size in [4300, 4350] {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .split(&html)?;
+            assert!(chunks.iter().any(|chunk| chunk.contains(code_block)));
+
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .atomic_tags(&[])
+                .split(&html)?;
+            assert!(chunks.iter().all(|chunk| !chunk.contains(code_block)));
+        }
+
+        for max_chunk_size in (200..400).step_by(50) {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .split(&html)?;
+            for code in [
+                "<code>vacant_rooms</code>",
+                "<code>unreachable_unchecked</code>",
+            ] {
+                assert!(chunks.iter().any(|chunk| chunk.contains(code)), "{code}");
+            }
+            assert_eq!(clean(chunks.concat()), clean(&html));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_items_kept_together() -> TestResult {
+        let items = [
+            "<li>first item</li>",
+            "<li>the second item</li>",
+            "<li>third</li>",
+            "<li>and the fourth one</li>",
+        ];
+        let html = format!("<ul>{}</ul>", items.concat());
+
+        for max_chunk_size in 40..64 {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .split(&html)?;
+            for chunk in &chunks {
+                let inner = &chunk["<ul>".len()..chunk.len() - "</ul>".len()];
+                assert!(
+                    inner.starts_with("<li>") && inner.ends_with("</li>"),
+                    "{max_chunk_size}: {chunks:?}"
+                );
+            }
+            assert_eq!(clean(chunks.concat()), clean(&html));
+        }
+
+        // an item that doesn't fit on its own is split anyway
+        let chunks = SplitOptions::default().max_chunk_size(30).split(&html)?;
+        assert!(chunks.contains(&"<ul><li>the second </li></ul>".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_with() -> TestResult {
+        let url = format!("https://example.com/{}", "very/long/path/".repeat(4));
+        let html = format!("<a href=\"{url}\">{}</a>", "link text ".repeat(20));
+        let options = SplitOptions::default().max_chunk_size(120);
+
+        let full = options.clone().split(&html)?;
+        let bare = options.reopen_with(bare_tag).split(&html)?;
+
+        assert!(full[1].starts_with(&format!("<a href=\"{url}\">")));
+        assert!(bare[1].starts_with("<a>text"));
+        assert!(bare.len() < full.len());
+        assert_eq!(bare[0].len(), full[0].len());
+
+        let content_len = |chunk: &String| clean(chunk).len();
+        assert!(content_len(&bare[1]) > content_len(&full[1]));
+        assert_eq!(clean(bare.concat()), clean(&html));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_tags_disabled() -> TestResult {
+        for max_chunk_size in (60..400).step_by(9) {
+            let chunks = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .reopen_tags(false)
+                .split(LONG_HTML)?;
+
+            let mut position = 0;
+            for chunk in &chunks {
+                assert!(chunk.len() <= max_chunk_size, "{chunk}");
+                assert!(LONG_HTML[position..].starts_with(chunk.as_str()), "{chunk}");
+                position += chunk.len();
+            }
+            assert_eq!(position, LONG_HTML.len());
+        }
+
+        let chunks = SplitOptions::default()
+            .max_chunk_size(16)
+            .reopen_tags(false)
+            .split("<b>bold words here</b> plain")?;
+        assert_eq!(chunks, vec!["<b>bold words ", "here</b>", " plain"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hard_split_tags() -> TestResult {
+        let html =
+            "<p>The first paragraph.</p>\n<p>The second one.</p>\n<p class='x'>The third.</p>";
+
+        let chunks = SplitOptions::default()
+            .hard_split_tags(&["p"])
+            .split(html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<p>The first paragraph.</p>",
+                "\n<p>The second one.</p>",
+                "\n<p class='x'>The third.</p>"
+            ]
+        );
+
+        let chunks = SplitOptions::default()
+            .hard_split_tags(&["p[class=x]"])
+            .split(html)?;
+        assert_eq!(chunks, vec![html]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_break_after() -> TestResult {
+        let options = SplitOptions::default()
+            .break_after(&["hr"])
+            .soft_break_after(&["br"]);
+        assert_eq!(options.split("a<br>b<hr>c")?, vec!["a<br>b<hr>", "c"]);
+        assert_eq!(
+            options.split("<div>a<br>b<hr>c</div>d")?,
+            vec!["<div>a<br>b<hr></div>", "<div>c</div>d"]
+        );
+        assert_eq!(
+            SplitOptions::default().split("a<br>b<hr>c")?,
+            vec!["a<br>b<hr>c"]
+        );
+
+        // the text after a soft break goes to the next chunk only if it doesn't fit
+        let html = "<p>first line<br>second line</p>";
+        assert_eq!(
+            options.clone().max_chunk_size(30).split(html)?,
+            vec!["<p>first line<br></p>", "<p>second line</p>"]
+        );
+        assert_eq!(
+            SplitOptions::default().max_chunk_size(30).split(html)?,
+            vec!["<p>first line<br>second </p>", "<p>line</p>"]
+        );
+        assert_eq!(options.max_chunk_size(40).split(html)?, vec![html]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_depth() -> TestResult {
+        let html = format!("{}text{}", "<span>".repeat(200), "</span>".repeat(200));
+        let options = SplitOptions::default().max_chunk_size(4096);
+
+        let result = options.clone().max_depth(64).split(&html);
+        assert!(matches!(result, Err(SplitError::MaxDepthExceeded(65))));
+        assert_eq!(
+            options.clone().max_depth(200).split(&html)?,
+            vec![html.clone()]
+        );
+
+        let tg = TokenGroup::from_string(&html);
+        let result = tg.subdivide_with(&options.max_chunk_size(2000).max_depth(64));
+        assert!(matches!(result, Err(SplitError::MaxDepthExceeded(65))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_tags_per_chunk() -> TestResult {
+        let html = "<b>x</b> ".repeat(20);
+        assert_eq!(SplitOptions::default().split(&html)?, vec![html.clone()]);
+
+        let options = SplitOptions::default().max_tags_per_chunk(Some(8));
+        let chunks = options.split(&html)?;
+        assert!(chunks.len() >= 3, "{chunks:?}");
+        assert!(chunks.iter().all(|chunk| chunk.matches("<b>").count() <= 8));
+        assert_eq!(chunks.concat(), html);
+
+        // the tags reopened in a chunk count too
+        let html = format!("<p>{}</p>", "<b>x</b> ".repeat(20));
+        let chunks = options.split(&html)?;
+        assert!(chunks.len() >= 3, "{chunks:?}");
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.matches("<b>").count() + chunk.matches("<p>").count() <= 8));
+        assert_eq!(clean(chunks.concat()), clean(&html));
+
+        let html = "<i><i><i>text</i></i></i>";
+        assert!(matches!(
+            SplitOptions::default()
+                .max_tags_per_chunk(Some(2))
+                .split(html),
+            Err(SplitError::SubdivisionImpossible { .. })
+        ));
+
+        let result = SplitOptions::default()
+            .max_tags_per_chunk(Some(0))
+            .split(html);
+        assert!(
+            matches!(
+                result,
+                Err(SplitError::InvalidOption {
+                    option: "max_tags_per_chunk",
+                    value: 0
+                })
+            ),
+            "{result:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_balanced_distribution() -> TestResult {
+        fn variance(chunks: &[String]) -> f64 {
+            let avg = chunks.iter().map(String::len).sum::<usize>() as f64 / chunks.len() as f64;
+            chunks
+                .iter()
+                .map(|chunk| (chunk.len() as f64 - avg).powi(2))
+                .sum::<f64>()
+                / chunks.len() as f64
+        }
+
+        for chunk_size in [150, 200, 500] {
+            let options = SplitOptions::default().max_chunk_size(chunk_size);
+            let greedy = options.split(LONG_HTML)?;
+            let balanced = options
+                .clone()
+                .distribution(Distribution::Balanced)
+                .split(LONG_HTML)?;
+
+            assert_eq!(balanced.len(), greedy.len());
+            assert!(balanced.iter().all(|chunk| chunk.len() <= chunk_size));
+            assert!(balanced.iter().map(String::len).max() <= greedy.iter().map(String::len).max());
+            assert!(
+                variance(&balanced) < variance(&greedy),
+                "{chunk_size}: {balanced:?}"
+            );
+            assert_eq!(clean(balanced.concat()), clean(LONG_HTML));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_last_distribution() -> TestResult {
+        let html = "<p>1</p><p>2</p><p>3</p><p>4</p>";
+        let options = SplitOptions::default().max_chunk_size(24);
+        assert_eq!(
+            options.split(html)?,
+            vec!["<p>1</p><p>2</p><p>3</p>", "<p>4</p>"]
+        );
+        assert_eq!(
+            options
+                .clone()
+                .distribution(Distribution::FillFirst)
+                .split(html)?,
+            options.split(html)?
+        );
+        assert_eq!(
+            options
+                .clone()
+                .distribution(Distribution::FillLast)
+                .split(html)?,
+            vec!["<p>1</p>", "<p>2</p><p>3</p><p>4</p>"]
+        );
+
+        // the short chunk is the first one, so it's the one balanced
+        assert_eq!(
+            options
+                .clone()
+                .distribution(Distribution::FillLast)
+                .min_chunk_size(10)
+                .split(html)?,
+            vec!["<p>1</p><p>2</p>", "<p>3</p><p>4</p>"]
+        );
+
+        // the chunks packed before an error are yielded first, as with the greedy distribution
+        let html = "<p>1</p><p>2</p><p>3</p><p>4</p><b>5</i>";
+        let chunks = options
+            .clone()
+            .distribution(Distribution::FillLast)
+            .split_iter(html)
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            chunks.as_slice(),
+            [Ok(first), Ok(last), Err(SplitError::UnbalancedToken(_))]
+                if first == "<p>1</p>" && last == "<p>2</p><p>3</p><p>4</p>"
+        ));
+
+        // a hard split still ends the chunk of its element
+        let chunks = options
+            .clone()
+            .distribution(Distribution::FillLast)
+            .hard_split_tags(&["p"])
+            .split("<p>1</p>2<p>3</p>4")?;
+        assert_eq!(chunks, vec!["<p>1</p>", "2<p>3</p>", "4"]);
+
+        let chunks = options
+            .max_chunk_size(200)
+            .distribution(Distribution::FillLast)
+            .split(LONG_HTML)?;
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 200));
+        assert_eq!(clean(chunks.concat()), clean(LONG_HTML));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerance() -> TestResult {
+        // the code element is 5 bytes over the limit
+        let html = "<b>intro</b> <code>let x = 12345678;</code> outro";
+        let options = SplitOptions::default()
+            .max_chunk_size(25)
+            .no_split(&["code"]);
+
+        let result = options.split(html);
+        assert!(matches!(result, Err(SplitError::SplitExceededTheLimit(_))));
+        let result = options.clone().tolerance(2).split(html);
+        assert!(matches!(result, Err(SplitError::SplitExceededTheLimit(_))));
+
+        let chunks = options.tolerance(8).split(html)?;
+        assert_eq!(
+            chunks,
+            vec!["<b>intro</b> ", "<code>let x = 12345678;</code>", " outro"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_map() -> TestResult {
+        let options = SplitOptions::default().max_chunk_size(100);
+        let chunks = options.split(LONG_HTML)?;
+
+        let numbered = options.split_map(LONG_HTML, |index, chunk| {
+            Ok::<_, OwnedSplitError>(format!("{index}: {chunk}"))
+        })?;
+        assert_eq!(numbered.len(), chunks.len());
+        for (index, (numbered, chunk)) in numbered.iter().zip(&chunks).enumerate() {
+            assert_eq!(numbered, &format!("{index}: {chunk}"));
+        }
+
+        #[derive(Debug, PartialEq)]
+        enum MapError {
+            Split(OwnedSplitError),
+            TooMany(usize),
+        }
+        impl From<SplitError<'_>> for MapError {
+            fn from(err: SplitError<'_>) -> Self {
+                MapError::Split(err.into_owned())
+            }
+        }
+
+        let mut calls = 0;
+        let result = options.split_map(LONG_HTML, |index, chunk| {
+            calls += 1;
+            if index == 2 {
+                return Err(MapError::TooMany(index));
+            }
+            Ok(chunk)
+        });
+        assert_eq!(result, Err(MapError::TooMany(2)));
+        assert_eq!(calls, 3);
+
+        let result = options.split_map("</b>", |_, chunk| Ok::<_, MapError>(chunk));
+        assert_eq!(
+            result,
+            Err(MapError::Split(OwnedSplitError::UnbalancedToken(
+                "</b>".into()
+            )))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_to_writer() -> TestResult {
+        for chunk_size in [100, 200, 4096] {
+            let options = SplitOptions::default().max_chunk_size(chunk_size);
+            let mut buffer = vec![];
+            let count = options.split_to_writer(LONG_HTML, b"\n---\n", &mut buffer)?;
+
+            let chunks = options.split(LONG_HTML)?;
+            assert_eq!(count, chunks.len());
+            assert_eq!(String::from_utf8(buffer)?, chunks.join("\n---\n"));
+        }
+
+        let result =
+            SplitOptions::default().split_to_writer("<b>x</b>", b"", &mut [0u8; 2].as_mut());
+        assert!(matches!(result, Err(SplitError::Io(_))));
+
+        // the chunks that exceed the limit are written along with the rest
+        let html = "<p>text <a href='x'>a link too long to fit</a> tail</p>";
+        let options = SplitOptions::default().max_chunk_size(24).no_split(&["a"]);
+        let chunks = options
+            .split(html)
+            .unwrap_err()
+            .into_recovered_chunks()
+            .unwrap_or_default();
+        assert!(chunks.len() > 1, "{chunks:?}");
+        let mut buffer = vec![];
+        let result = options.split_to_writer(html, b"\n", &mut buffer);
+        assert!(
+            matches!(result, Err(SplitError::WrittenExceedingTheLimit(count)) if count == chunks.len()),
+            "{result:?}"
+        );
+        assert_eq!(String::from_utf8(buffer)?, chunks.join("\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_many() {
+        let docs = [LONG_HTML, "<b>bold</b> text", "", "</b>unbalanced"];
+        let options = SplitOptions::default().max_chunk_size(100);
+
+        let results = options.split_many(&docs);
+        assert_eq!(results.len(), docs.len());
+        for (doc, result) in docs.iter().zip(results) {
+            assert_eq!(result, options.split(doc).map_err(SplitError::into_owned));
+        }
+    }
+
+    #[test]
+    fn test_split_with_metadata() -> TestResult {
+        let html =
+            "<p>intro</p><code>let a = 1;</code><p>some more text</p><code>let b = 2;</code>";
+        let options = SplitOptions::default()
+            .max_chunk_size(40)
+            .no_split(&["code"]);
+        let chunks = options.split_with_metadata(html)?;
+        assert_eq!(
+            chunks.iter().map(|(chunk, _)| chunk).collect::<Vec<_>>(),
+            options.split(html)?.iter().collect::<Vec<_>>()
+        );
+
+        let first = 12..35;
+        let second = 56..79;
+        assert_eq!(&html[first.clone()], "<code>let a = 1;</code>");
+        assert_eq!(&html[second.clone()], "<code>let b = 2;</code>");
+        let blocks = chunks
+            .iter()
+            .map(|(_, metadata)| metadata.no_split_blocks.clone())
+            .collect::<Vec<_>>();
+        // every block is listed once, and the paragraphs are not listed
+        assert_eq!(blocks.concat(), vec![first.clone(), second.clone()]);
+        let position = |block| blocks.iter().position(|blocks| blocks.contains(block));
+        assert!(position(&first) < position(&second), "{chunks:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_with_stats() -> TestResult {
+        for chunk_size in [100, 200, 4096] {
+            let options = SplitOptions::default().max_chunk_size(chunk_size);
+            let (chunks, stats) = options.split_with_stats(LONG_HTML)?;
+            assert_eq!(chunks, options.split(LONG_HTML)?);
+
+            let lens = chunks.iter().map(String::len).collect::<Vec<_>>();
+            assert_eq!(stats.count, chunks.len());
+            assert!(stats.max_len <= chunk_size);
+            assert_eq!(stats.min_len, *lens.iter().min().unwrap());
+            assert_eq!(stats.max_len, *lens.iter().max().unwrap());
+            assert_eq!(
+                stats.avg_len,
+                lens.iter().sum::<usize>() as f64 / chunks.len() as f64
+            );
+        }
+
+        let (_, stats) = SplitOptions::default()
+            .max_chunk_size(30)
+            .split_with_stats("<b>bold <i>italic and bold</i></b>")?;
+        // `<b>bold <i>italic and </i></b>`, `<b><i>bold</i></b>`
+        assert_eq!(stats.reopened_tag_count, 2);
+
+        let (chunks, stats) = SplitOptions::default().split_with_stats("")?;
+        assert!(chunks.is_empty());
+        assert_eq!(stats, SplitStats::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_normalized() -> TestResult {
+        let html = "\u{feff}<p>first line\r\nsecond line\rthird</p>\r\n<b>x</b>";
+        let options = SplitOptions::default().max_chunk_size(24);
+        let normalization = Normalization::default()
+            .strip_bom(true)
+            .normalize_newlines(true);
+
+        assert!(matches!(
+            options.normalize("<b>x</b>\n", normalization),
+            Cow::Borrowed(_)
+        ));
+        let chunks = options.split_normalized(html, normalization)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<p>first line\nsecond</p>",
+                "<p> line\nthird</p>",
+                "\n<b>x</b>"
+            ]
+        );
+
+        let chunks = options.split_normalized(html, normalization.normalize_newlines(false))?;
+        assert!(chunks[0].starts_with("<p>"));
+        assert!(chunks.concat().contains("\r\n"));
+
+        // splitting as is keeps the input intact
+        assert_eq!(
+            SplitOptions::default().split("\u{feff}a\r\nb")?,
+            vec!["\u{feff}a\r\nb"]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_atomic_pattern() -> TestResult {
+        let html = "<b>sum $x + y$ here</b>";
+        let options = SplitOptions::default().max_chunk_size(16);
+        assert_eq!(
+            options.split(html)?,
+            vec!["<b>sum $x + </b>", "<b>y$ here</b>"]
+        );
+
+        let options = options.atomic_pattern(regex::Regex::new(r"\$[^$]+\$")?);
+        assert_eq!(
+            options.split(html)?,
+            vec!["<b>sum </b>", "<b>$x + y$ </b>", "<b>here</b>"]
+        );
+        assert_eq!(options.minimum_chunk_size(html)?, 14);
+        assert!(options.max_chunk_size(13).split(html).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_break_chars() -> TestResult {
+        let html = "<code>/usr/local/share/doc</code> path";
+        let options = SplitOptions::default()
+            .max_chunk_size(24)
+            .atomic_tags(&[])
+            .break_chars(|ch| ch == '/');
+        let chunks = options.split(html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<code>/usr/local/</code>",
+                "<code>share/doc</code>",
+                " path"
+            ]
+        );
+
+        let options = options.word_break(WordBreak::Never);
+        assert_eq!(options.split(html)?, chunks);
+        assert!(SplitOptions::default()
+            .max_chunk_size(24)
+            .atomic_tags(&[])
+            .word_break(WordBreak::Never)
+            .split(html)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cut_marker() -> TestResult {
+        let word = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWX";
+        let options = SplitOptions::default().max_chunk_size(10).cut_marker("-");
+
+        let chunks = options.split(word)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "abcdefghi-",
+                "jklmnopqr-",
+                "stuvwxyzA-",
+                "BCDEFGHIJ-",
+                "KLMNOPQRS-",
+                "TUVWX"
+            ]
+        );
+        assert_eq!(chunks.concat().replace('-', ""), word);
+
+        let chunks = options.split(&format!("<b>{word}</b> and more"))?;
+        assert_eq!(chunks[0], "<b>ab-</b>");
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 10));
+        assert_eq!(chunks.last().map(String::as_str), Some(" and more"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_continuation_markers() -> TestResult {
+        let html = "<p>one two</p><p>three</p><a href=\"x\">four five six seven</a>";
+        let options = SplitOptions::default()
+            .max_chunk_size(30)
+            .continuation_markers(" >", "> ");
+
+        let chunks = options.split(html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<p>one two</p><p>three</p>",
+                "<a href=\"x\">four five </a> >",
+                "> <a href=\"x\">six seven</a>"
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 30));
+
+        // a cut where no tag is open is not a seam
+        let chunks = options
+            .clone()
+            .split("one two three four five six seven eight")?;
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| !chunk.contains('>')));
+
+        // the chunks subdivided again to balance a short tail are marked once
+        let chunks = SplitOptions::default()
+            .max_chunk_size(16)
+            .continuation_markers("~", "")
+            .min_chunk_size(12)
+            .split("<b>aaaa bbbb cccc dddd eeee ffff</b>")?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<b>aaaa </b>~",
+                "<b>bbbb </b>~",
+                "<b>cccc </b>~",
+                "<b>dddd </b>~",
+                "<b>eeee ffff</b>"
+            ]
+        );
+
+        // the overlap repeats the content, not the markers
+        let chunks = options
+            .clone()
+            .max_chunk_size(40)
+            .overlap(12)
+            .split("<b>one two three four five six seven eight nine</b>")?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<b>one two three </b> >",
+                "> <b>four five six </b> >",
+                "<b>six </b>> <b>seven eight nine</b>"
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 40));
+
+        assert!(matches!(
+            options.max_chunk_size(4).split(html),
+            Err(SplitError::InvalidLen(4))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_protect_urls() -> TestResult {
+        let url = "https://example.com/some/long/path?q=1";
+        let long_url = "https://example.com/a/very/long/path/that/does/not/fit";
+        let html = format!("<p>Read <b>more</b>{url} today {long_url} end</p>");
+        let options = SplitOptions::default().max_chunk_size(48);
+
+        let chunks = options.clone().split(&html)?;
+        assert!(!chunks.iter().any(|chunk| chunk.contains(url)));
+
+        let chunks = options.protect_urls(true).split(&html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<p>Read <b>more</b></p>".to_string(),
+                format!("<p>{url} </p>"),
+                "<p>today </p>".to_string(),
+                "<p>https://example.com/a/very/long/path/that</p>".to_string(),
+                "<p>/does/not/fit end</p>".to_string(),
+            ]
+        );
+        assert_eq!(clean(chunks.concat()), clean(html));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_local_names() {
+        let options = SplitOptions::default();
+        let token = Token::OpenTag("<svg:rect width='10'>", 0);
+        assert!(options.clone().no_split(&["svg:rect"]).is_no_split(&token));
+        assert!(!options.clone().no_split(&["rect"]).is_no_split(&token));
+        assert!(options
+            .clone()
+            .no_split(&["rect"])
+            .match_local_names(true)
+            .is_no_split(&token));
+        assert!(options
+            .no_split(&["svg:rect"])
+            .match_local_names(true)
+            .is_no_split(&token));
+    }
+
+    #[test]
+    fn test_normalize_self_closing() -> TestResult {
+        let html = "<b/>text<br/><i>more</i>";
+        assert_eq!(SplitOptions::default().split(html)?, vec![html]);
+
+        let options = SplitOptions::default().normalize_self_closing(true);
+        assert_eq!(options.split(html)?, vec!["<b></b>text<br><i>more</i>"]);
+        assert_eq!(
+            options.max_chunk_size(13).split(html)?,
+            vec!["<b></b>text<br>", "<i>more</i>"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_void_tags() -> TestResult {
+        let html = "a<br>b<img src=x>c";
+        assert_eq!(SplitOptions::default().split(html)?, vec![html]);
+        assert_eq!(
+            SplitOptions::default().max_chunk_size(13).split(html)?,
+            vec!["a<br>b", "<img src=x>", "c"]
+        );
+
+        let html = "a<x-icon>b";
+        assert!(matches!(
+            SplitOptions::default().split(html),
+            Err(SplitError::UnbalancedToken(_))
+        ));
+        let options = SplitOptions::default().void_tags(&["X-ICON"]);
+        assert_eq!(options.split(html)?, vec![html]);
+
+        // not a void element anymore, so it needs a close tag
+        assert!(matches!(
+            options.split("a<br>b"),
+            Err(SplitError::UnbalancedToken(_))
+        ));
+        assert_eq!(options.split("a<br>b</br>")?, vec!["a<br>b</br>"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collapse_whitespace() -> TestResult {
+        let html = "<p>one   two\n\n  three</p>\t <pre>a   b\n  c</pre>";
+        let options = SplitOptions::default();
+        let normalization = Normalization::default().collapse_whitespace(true);
+        assert_eq!(
+            options.normalize(html, normalization),
+            "<p>one two three</p> <pre>a   b\n  c</pre>"
+        );
+        assert!(matches!(
+            options.normalize("<p>one two</p>", normalization),
+            Cow::Borrowed(_)
+        ));
+        let script = "<script>// hi\nrun();</script> <style>a  {}</style>";
+        assert_eq!(options.normalize(script, normalization), script);
+
+        // the collapsed paragraph fits, the raw one doesn't
+        let options = options.max_chunk_size(24);
+        assert_eq!(
+            options.split_normalized(html, normalization)?,
+            vec!["<p>one two three</p> ", "<pre>a   b\n  c</pre>"]
+        );
+        assert_eq!(
+            options.split_normalized(html, Normalization::default())?[0],
+            "<p>one   two\n\n  </p>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> TestResult {
+        for max_chunk_size in (64..2048).step_by(61) {
+            let options = SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .no_split(&["a"])
+                .verify(true);
+            match options.split(LONG_HTML) {
+                Ok(_) | Err(SplitError::SplitExceededTheLimit(_)) => {}
+                Err(SplitError::SubdivisionImpossible { .. }) if max_chunk_size < 256 => {}
+                Err(err) => panic!("{max_chunk_size}: {err}"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict() -> TestResult {
+        let options = SplitOptions::default().max_chunk_size(16).strict(true);
+        let malformed = [
+            ("<b>bold</b> <i", 12, "<i"),
+            ("text <b>a</b> < b", 14, "< b"),
+            ("<b>a</b> <> b", 9, "<>"),
+            ("text <!-- unterminated", 5, "<!-- unterminated"),
+        ];
+        for (html, expected_at, expected_snippet) in malformed {
+            assert!(SplitOptions::default().split(html).is_ok());
+            match options.split(html) {
+                Err(SplitError::MalformedTag { at, snippet }) => {
+                    assert_eq!((at, snippet), (expected_at, expected_snippet));
+                }
+                result => panic!("{html}: {result:?}"),
+            }
+        }
+
+        let html = "<b>bold</b> text <!-- comment -->";
+        assert_eq!(
+            options.split(html)?,
+            SplitOptions::default().max_chunk_size(16).split(html)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_empty_tags() -> TestResult {
+        let html = r#"<a id="anchor"></a><p>Some <b>long text<span id="x"></span> here</b></p>"#;
+        let options = SplitOptions::default().max_chunk_size(40);
+
+        assert_eq!(
+            options.clone().split(html)?,
+            vec!["<p>Some <b>long text here</b></p>"]
+        );
+        assert_eq!(
+            options.keep_empty_tags(true).split(html)?,
+            vec![
+                r#"<a id="anchor"></a>"#,
+                "<p>Some <b>long text</b></p>",
+                r#"<p><b><span id="x"></span> here</b></p>"#,
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_break_at_graphemes() -> TestResult {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let html = format!("<b>a{family}e\u{301}e\u{301}</b>");
+        let options = SplitOptions::default()
+            .max_chunk_size(12)
+            .length_mode(LengthMode::Chars);
+
+        assert_eq!(
+            options
+                .clone()
+                .word_break(WordBreak::AtGraphemes)
+                .split(&html)?,
+            vec![
+                "<b>a</b>".to_string(),
+                format!("<b>{family}</b>"),
+                "<b>e\u{301}e\u{301}</b>".to_string(),
+            ]
+        );
+        // the default one cuts right after the ZWJ
+        assert_eq!(options.split(&html)?[0], "<b>a👨\u{200d}👩\u{200d}</b>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_chunks() -> TestResult {
+        let html = "<p>one two three four</p>\n<pre>  a  </pre> <b> five <br> </b> six ";
+        let options = SplitOptions::default().max_chunk_size(16).trim_chunks(true);
+        let chunks = options.split(html)?;
+        assert_eq!(
+            chunks,
+            vec![
+                "<p>one two</p>",
+                "<p>three</p>",
+                "<p>four</p>",
+                "<pre>  a  </pre>",
+                "<b>five</b>",
+                "<b><br></b>",
+                "six"
+            ]
+        );
+        for chunk in &chunks {
+            assert!(chunk.starts_with('<') || !chunk.starts_with(char::is_whitespace));
+            assert!(!chunk.ends_with(char::is_whitespace));
+        }
+
+        let without_whitespace = |text: String| text.split_whitespace().collect::<String>();
+        assert_eq!(
+            without_whitespace(crate::clean(chunks.concat())),
+            without_whitespace(crate::clean(html))
+        );
+        assert_eq!(
+            options.clone().trim_chunks(false).split(html)?[0],
+            "<p>one two </p>"
+        );
+
+        let finish = |chunk: &str| options.finish_chunk(chunk.to_string());
+        assert_eq!(finish("<b> a </b>").as_deref(), Some("<b>a</b>"));
+        assert_eq!(finish("  <br> a").as_deref(), Some("<br> a"));
+        assert_eq!(
+            finish("<i></i> <b> </b>").as_deref(),
+            Some("<i></i><b></b>")
+        );
+        assert_eq!(finish(" \n "), None);
+        assert_eq!(
+            finish(" <script>\n</script> ").as_deref(),
+            Some("<script>\n</script>")
+        );
+
+        // the configured void elements are content too
+        let options = options.void_tags(&["br", "x-icon"]);
+        assert_eq!(
+            options
+                .finish_chunk(" text <x-icon> ".to_string())
+                .as_deref(),
+            Some("text <x-icon>")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::SplitError;
+use crate::length_mode::LengthMode;
+use crate::options::SplitOptions;
+use crate::token_group::{RootTokenGroups, TokenGroup};
+
+/// A document tokenized once and split as many times as needed, e.g. to try several chunk sizes.
+/// The splits pack the same root-level groups instead of tokenizing the text again.
+///
+/// ```
+/// use dumb_html_splitter::prepared::PreparedDocument;
+///
+/// let document = PreparedDocument::new("<p>one two</p><p>three <b>four</b></p>").unwrap();
+/// assert_eq!(document.split(30, &[]).unwrap().len(), 2);
+/// assert_eq!(document.split(100, &[]).unwrap().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PreparedDocument<'a> {
+    groups: Vec<TokenGroup<'a>>,
+}
+
+impl<'a> PreparedDocument<'a> {
+    /// Tokenizes `html` into its root-level groups, see [`crate::prepare_token_groups`]
+    pub fn new(html: &'a str) -> Result<Self, SplitError<'a>> {
+        Self::with_length_mode(html, LengthMode::default())
+    }
+
+    /// Same as [`Self::new`], but the groups are measured in `length_mode` units, which the
+    /// options of [`Self::split_with`] should use too
+    pub fn with_length_mode(
+        html: &'a str,
+        length_mode: LengthMode,
+    ) -> Result<Self, SplitError<'a>> {
+        Ok(Self {
+            groups: RootTokenGroups::new(html, length_mode).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn groups(&self) -> &[TokenGroup<'a>] {
+        &self.groups
+    }
+
+    /// Same as [`crate::split`] for the text the document was prepared from
+    pub fn split(
+        &self,
+        max_chunk_size: usize,
+        no_split: &[&str],
+    ) -> Result<Vec<String>, SplitError<'a>> {
+        self.split_with(
+            &SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .no_split(no_split),
+        )
+    }
+
+    /// Same as [`SplitOptions::split`] for the text the document was prepared from, see
+    /// [`SplitOptions::split_prepared`]
+    pub fn split_with(&self, options: &SplitOptions) -> Result<Vec<String>, SplitError<'a>> {
+        options.split_prepared(&self.groups)
+    }
+}
+
+impl<'a> From<Vec<TokenGroup<'a>>> for PreparedDocument<'a> {
+    fn from(groups: Vec<TokenGroup<'a>>) -> Self {
+        Self { groups }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split;
+    use crate::test_data::{LONG_HTML, SHORT_HTML};
+    use testresult::TestResult;
+
+    #[test]
+    fn test_split_at_many_sizes() -> TestResult {
+        let document = PreparedDocument::new(LONG_HTML)?;
+        for chunk_size in 100..2000 {
+            let Ok(expected) = split(LONG_HTML, chunk_size, &["a"]) else {
+                assert!(document.split(chunk_size, &["a"]).is_err());
+                continue;
+            };
+            assert_eq!(document.split(chunk_size, &["a"])?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_length_mode() -> TestResult {
+        let html = "<b>сорок два</b> и <i>ещё немного</i>";
+        let document = PreparedDocument::with_length_mode(html, LengthMode::Utf16)?;
+        let options = SplitOptions::default()
+            .max_chunk_size(20)
+            .length_mode(LengthMode::Utf16);
+        assert_eq!(document.split_with(&options)?, options.split(html)?);
+
+        let document = PreparedDocument::from(crate::prepare_token_groups(SHORT_HTML)?);
+        assert_eq!(document.groups().len(), 1);
+        assert_eq!(document.split(1000, &[])?, vec![SHORT_HTML]);
+        Ok(())
+    }
+}
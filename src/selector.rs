@@ -0,0 +1,151 @@
+use crate::token::Token;
+
+/// How an attribute of a [`Selector`] is matched
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum AttributeMatch {
+    /// `[name]`
+    Present,
+    /// `[name=value]`
+    Equals(String),
+    /// `[name~=value]`, the value is one of the whitespace-separated words
+    Contains(String),
+}
+
+/// A tiny subset of CSS selectors: a tag name followed by attribute conditions, like `span`,
+/// `span[class~=tg-spoiler]` or `[data-atomic]`. The `*` tag name matches any tag, and so does a
+/// missing one as long as there's an attribute condition; an empty selector matches nothing.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Selector {
+    tag_name: String,
+    attributes: Vec<(String, AttributeMatch)>,
+}
+
+impl Selector {
+    pub(crate) fn parse(selector: &str) -> Self {
+        let selector = selector.trim();
+        let (tag_name, mut rest) = selector.split_at(selector.find('[').unwrap_or(selector.len()));
+
+        let mut attributes = vec![];
+        while let Some(condition) = rest.strip_prefix('[') {
+            let end = condition.find(']').unwrap_or(condition.len());
+            rest = condition.get(end + 1..).unwrap_or("");
+
+            let condition = &condition[..end];
+            let attribute = match condition.split_once('=') {
+                None => (condition.trim(), AttributeMatch::Present),
+                Some((name, value)) => {
+                    let value = value.trim().trim_matches(['"', '\'']).to_string();
+                    match name.strip_suffix('~') {
+                        Some(name) => (name.trim(), AttributeMatch::Contains(value)),
+                        None => (name.trim(), AttributeMatch::Equals(value)),
+                    }
+                }
+            };
+            attributes.push((attribute.0.to_string(), attribute.1));
+        }
+
+        Self {
+            tag_name: tag_name.to_string(),
+            attributes,
+        }
+    }
+
+    /// Attribute names are always matched ignoring the ASCII case, the values are matched exactly.
+    /// With `local_names`, the tag name may also match the one without the namespace prefix.
+    pub(crate) fn matches(
+        &self,
+        token: &Token,
+        case_sensitive_tags: bool,
+        local_names: bool,
+    ) -> bool {
+        let is_name_matched = |tag_name: &str| {
+            if case_sensitive_tags {
+                self.tag_name == tag_name
+            } else {
+                self.tag_name.eq_ignore_ascii_case(tag_name)
+            }
+        };
+        let is_tag_matched = self.tag_name.is_empty() && !self.attributes.is_empty()
+            || self.tag_name == "*"
+            || is_name_matched(token.tag_name())
+            || local_names && is_name_matched(token.local_tag_name());
+        if !is_tag_matched {
+            return false;
+        }
+        if self.attributes.is_empty() {
+            return true;
+        }
+
+        let token_attributes = token.attributes().collect::<Vec<_>>();
+        self.attributes.iter().all(|(name, condition)| {
+            token_attributes
+                .iter()
+                .filter(|(token_name, _)| name.eq_ignore_ascii_case(token_name))
+                .map(|(_, value)| value.unwrap_or(""))
+                .any(|value| match condition {
+                    AttributeMatch::Present => true,
+                    AttributeMatch::Equals(expected) => value == expected,
+                    AttributeMatch::Contains(expected) => {
+                        value.split_whitespace().any(|word| word == expected)
+                    }
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Selector::parse("span[class~=tg-spoiler][data-id='1']"),
+            Selector {
+                tag_name: "span".into(),
+                attributes: vec![
+                    (
+                        "class".into(),
+                        AttributeMatch::Contains("tg-spoiler".into())
+                    ),
+                    ("data-id".into(), AttributeMatch::Equals("1".into())),
+                ],
+            }
+        );
+        assert_eq!(
+            Selector::parse("[hidden]"),
+            Selector {
+                tag_name: "".into(),
+                attributes: vec![("hidden".into(), AttributeMatch::Present)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let token = Token::OpenTag(r#"<SPAN class="tg-spoiler big" hidden>"#, 0);
+
+        assert!(Selector::parse("span").matches(&token, false, false));
+        assert!(!Selector::parse("span").matches(&token, true, false));
+        assert!(Selector::parse("span[class~=big]").matches(&token, false, false));
+        assert!(!Selector::parse("span[class=big]").matches(&token, false, false));
+        assert!(Selector::parse("span[class='tg-spoiler big']").matches(&token, false, false));
+        assert!(Selector::parse("[HIDDEN]").matches(&token, false, false));
+        assert!(!Selector::parse("span[title]").matches(&token, false, false));
+        assert!(!Selector::parse("code").matches(&token, false, false));
+
+        assert!(!Selector::parse("").matches(&token, false, false));
+        assert!(!Selector::parse(" ").matches(&token, false, true));
+
+        assert!(Selector::parse("*").matches(&token, true, false));
+        assert!(Selector::parse("*[hidden]").matches(&token, false, false));
+        assert!(!Selector::parse("*[title]").matches(&token, false, false));
+
+        let token = Token::OpenTag("<svg:rect>", 0);
+        assert!(Selector::parse("svg:rect").matches(&token, false, false));
+        assert!(Selector::parse("svg:rect").matches(&token, false, true));
+        assert!(!Selector::parse("rect").matches(&token, false, false));
+        assert!(Selector::parse("RECT").matches(&token, false, true));
+        assert!(!Selector::parse("circle").matches(&token, false, true));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::error::SplitError;
+use crate::options::{Distribution, SplitOptions};
+use crate::token::Token;
+use crate::token_group::{RootTokenGroups, TokenGroup};
+use std::collections::VecDeque;
+
+/// What [`SplitIter`] packs into chunks: a document, or the root-level groups of one
+#[derive(Clone, Copy)]
+pub(crate) enum SplitInput<'a, 'b> {
+    Text(&'a str),
+    Groups(&'b [TokenGroup<'a>]),
+}
+
+/// The root-level groups, either tokenized on the go or prepared beforehand
+enum TokenGroups<'a> {
+    Text(RootTokenGroups<'a>),
+    Prepared(std::vec::IntoIter<TokenGroup<'a>>),
+}
+
+impl<'a> Iterator for TokenGroups<'a> {
+    type Item = Result<TokenGroup<'a>, SplitError<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TokenGroups::Text(tgs) => tgs.next(),
+            TokenGroups::Prepared(tgs) => tgs.next().map(Ok),
+        }
+    }
+}
+
+/// An iterator over the chunks of a document, see [`crate::split_iter`]
+pub struct SplitIter<'a> {
+    /// The source of the groups; empty if they were prepared beforehand
+    text: &'a str,
+    token_groups: TokenGroups<'a>,
+    options: SplitOptions,
+    overlap: String,
+    chunk: TokenGroup<'a>,
+    pending: VecDeque<Result<TokenGroup<'a>, SplitError<'a>>>,
+    is_done: bool,
+    keeps_last_chunk: bool,
+}
+
+impl<'a> SplitIter<'a> {
+    pub(crate) fn new(input: SplitInput<'a, '_>, mut options: SplitOptions) -> Self {
+        // we leave room for the overlap in every chunk
+        options.max_chunk_size = options.max_chunk_size.saturating_sub(options.overlap);
+        options.hard_max_chunk_size = options
+            .hard_max_chunk_size
+            .map(|hard| hard.saturating_sub(options.overlap));
+
+        let (text, token_groups) = match input {
+            SplitInput::Text(text) => (
+                text,
+                TokenGroups::Text(
+                    RootTokenGroups::new(text, options.length_mode)
+                        .max_depth(options.max_depth)
+                        .keep_empty_tags(options.keep_empty_tags)
+                        .void_tags(options.void_tags.clone())
+                        .strict(options.strict),
+                ),
+            ),
+            SplitInput::Groups(tgs) => ("", TokenGroups::Prepared(Vec::from(tgs).into_iter())),
+        };
+        let mut iter = Self {
+            text,
+            token_groups,
+            chunk: TokenGroup::new(options.length_mode)
+                .keep_empty_tags(options.keep_empty_tags)
+                .void_tags(options.void_tags.clone()),
+            options,
+            overlap: String::new(),
+            pending: VecDeque::new(),
+            is_done: false,
+            keeps_last_chunk: false,
+        };
+
+        // a chunk with no room, or with room only for the overlap, can't hold anything
+        if iter.options.max_chunk_size == 0 {
+            iter.is_done = true;
+            iter.pending
+                .push_back(Err(SplitError::InvalidLen(iter.options.overlap)));
+        } else if iter.options.max_tags_per_chunk == Some(0) {
+            // not even the tags reopened in a chunk would fit
+            iter.is_done = true;
+            iter.pending.push_back(Err(SplitError::InvalidOption {
+                option: "max_tags_per_chunk",
+                value: 0,
+            }));
+        }
+
+        iter
+    }
+
+    /// Renders the chunk after the tail of the previous one. The tail is kept in a buffer that
+    /// the next chunk is written into.
+    pub(crate) fn with_overlap(&mut self, tg: &TokenGroup) -> String {
+        if self.options.overlap == 0 || tg.tokens.is_empty() {
+            let mut chunk = String::new();
+            tg.write_to(&mut chunk);
+            return chunk;
+        }
+
+        let mut chunk = std::mem::take(&mut self.overlap);
+        let start = chunk.len();
+        tg.write_to(&mut chunk);
+
+        // the continuation markers are not the content to repeat
+        let (start_marker, end_marker) = tg.markers();
+        TokenGroup::from_string(&chunk[start + start_marker.len()..chunk.len() - end_marker.len()])
+            .with_length_mode(self.options.length_mode)
+            .tail(self.options.overlap, self.options.reopen_tags)
+            .write_to(&mut self.overlap);
+
+        chunk
+    }
+
+    /// Renders the chunk the way it's yielded: after the overlap, and trimmed if the options say
+    /// so; `None` if nothing is left of it
+    pub(crate) fn render(&mut self, tg: &TokenGroup) -> Option<String> {
+        let chunk = self.with_overlap(tg);
+        self.options.finish_chunk(chunk)
+    }
+
+    /// Keeps the chunk being packed when the text ends instead of yielding it, since more text
+    /// may still be appended to it, see [`Self::unfinished_start`]
+    pub(crate) fn keep_last_chunk(mut self) -> Self {
+        self.keeps_last_chunk = true;
+        self
+    }
+
+    /// The byte offset the kept last chunk starts at, if there's one
+    pub(crate) fn unfinished_start(&self) -> Option<usize> {
+        self.chunk.tokens.first().map(Token::index)
+    }
+
+    /// Merges a last chunk shorter than `min_chunk_size` into the previous one, or splits the two
+    /// again evenly if they don't fit into a single chunk, even past the hard limit. When packing
+    /// from the end, it's the first chunk that is left short, so it's merged into the next one.
+    fn rebalance_tail(&mut self) {
+        let is_packed_from_end = self.is_packed_from_end();
+        let short = match self.pending.make_contiguous() {
+            [Ok(first), Ok(_), ..] if is_packed_from_end => first,
+            [.., Ok(_), Ok(last)] if !is_packed_from_end => last,
+            _ => return,
+        };
+        if short.len >= self.options.min_chunk_size {
+            return;
+        }
+
+        let pair = if is_packed_from_end {
+            (self.pending.pop_front(), self.pending.pop_front())
+        } else {
+            let last = self.pending.pop_back();
+            (self.pending.pop_back(), last)
+        };
+        let (Some(Ok(previous)), Some(Ok(last))) = pair else {
+            unreachable!("the two chunks are checked above");
+        };
+        let balanced = self.rebalance(previous, last);
+        if is_packed_from_end {
+            for tg in balanced.into_iter().rev() {
+                self.pending.push_front(Ok(tg));
+            }
+        } else {
+            self.pending.extend(balanced.into_iter().map(Ok));
+        }
+    }
+
+    /// Two adjacent chunks, one of them short, as a single chunk or as two even ones
+    fn rebalance(&self, previous: TokenGroup<'a>, last: TokenGroup<'a>) -> Vec<TokenGroup<'a>> {
+        let combined = previous.merge(&last, self.text);
+        if combined.len <= self.options.hard_limit() && self.fits_tags(&[&combined]) {
+            return vec![combined];
+        }
+
+        // the smallest limit that still fits the text into two chunks
+        let mut balanced = vec![previous, last];
+        let (mut low, mut high) = (combined.len.div_ceil(2), self.options.max_chunk_size);
+        while low <= high {
+            let limit = (low + high) / 2;
+            match combined.subdivide_with(&self.options.clone().max_chunk_size(limit)) {
+                Ok(tgs) if tgs.len() <= 2 => {
+                    balanced = tgs;
+                    high = limit - 1;
+                }
+                _ => low = limit + 1,
+            }
+        }
+
+        balanced
+    }
+
+    /// Adds the group to the chunk being packed, in front of it when packing from the end
+    fn push_to_chunk(&mut self, tg: TokenGroup<'a>) {
+        if !self.is_packed_from_end() {
+            self.chunk.append(tg);
+            return;
+        }
+
+        let chunk = std::mem::replace(&mut self.chunk, tg);
+        self.chunk.append(chunk);
+    }
+
+    /// Whether a chunk may have the elements of all `tgs`, see
+    /// [`SplitOptions::max_tags_per_chunk`]
+    fn fits_tags(&self, tgs: &[&TokenGroup]) -> bool {
+        self.options
+            .max_tags_per_chunk
+            .is_none_or(|max_tags| tgs.iter().map(|tg| tg.tag_count()).sum::<usize>() <= max_tags)
+    }
+
+    /// Whether the group has an atomic or `no_split` element, which a chunk may exceed the soft
+    /// limit to keep whole, see [`SplitOptions::hard_max_chunk_size`]
+    fn has_atomic_element(&self, tg: &TokenGroup) -> bool {
+        tg.tokens.iter().any(|token| {
+            token.is_open_in(&self.options.void_tags)
+                && (self.options.is_atomic(token) || self.options.is_no_split(token))
+        })
+    }
+
+    fn is_packed_from_end(&self) -> bool {
+        self.options.distribution == Distribution::FillLast
+    }
+
+    /// Packs all the groups starting with the last one, see [`Distribution::FillLast`]. The
+    /// pending chunks are collected in reverse and put in order at the end. The groups before a
+    /// tokenizer error are packed the same way, and the error comes after their chunks.
+    fn pack_from_end(&mut self) {
+        let mut tgs = vec![];
+        let mut error = None;
+        for tg in self.token_groups.by_ref() {
+            match tg {
+                Ok(tg) => tgs.push(tg),
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+
+        for tg in tgs.into_iter().rev() {
+            if self.is_done {
+                break;
+            }
+            self.pack(tg);
+        }
+        self.flush();
+        self.pending.make_contiguous().reverse();
+        self.is_done = true;
+
+        match error {
+            Some(err) => self.pending.push_back(Err(err)),
+            None if self.options.min_chunk_size != 0 => self.rebalance_tail(),
+            None => {}
+        }
+    }
+
+    /// Moves the current chunk to the pending ones unless it's empty
+    fn flush(&mut self) {
+        if self.chunk.tokens.is_empty() {
+            return;
+        }
+
+        let chunk = TokenGroup::new(self.options.length_mode)
+            .keep_empty_tags(self.options.keep_empty_tags)
+            .void_tags(self.options.void_tags.clone());
+        self.pending
+            .push_back(Ok(std::mem::replace(&mut self.chunk, chunk)));
+    }
+
+    fn pack(&mut self, tg: TokenGroup<'a>) {
+        let is_hard_split = tg.tokens.first().is_some_and(|token| {
+            token.is_open_in(&self.options.void_tags) && self.options.is_hard_split(token)
+        }) && tg.tokens.last().is_some_and(|token| token.is_close());
+        let is_break = tg
+            .tokens
+            .last()
+            .is_some_and(|token| self.options.is_break_after(token));
+        let has_inner_breaks = tg.tokens.split_last().is_some_and(|(_, tokens)| {
+            tokens
+                .iter()
+                .any(|token| self.options.is_break_after(token))
+        });
+
+        // the group ends its chunk, which is already packed when packing from the end
+        let ends_chunk = is_hard_split || is_break;
+        if ends_chunk && self.is_packed_from_end() {
+            self.flush();
+        }
+        if has_inner_breaks {
+            self.pack_at_breaks(tg);
+        } else {
+            self.pack_group(tg);
+        }
+        if ends_chunk && !self.is_packed_from_end() {
+            self.flush();
+        }
+    }
+
+    /// Cuts the group after every `break_after` element inside it, then packs the pieces as usual
+    fn pack_at_breaks(&mut self, tg: TokenGroup<'a>) {
+        let options = self.options.clone().max_chunk_size(usize::MAX);
+        match tg.subdivide_with(&options) {
+            Ok(mut tgs) => {
+                if self.is_packed_from_end() {
+                    tgs.reverse();
+                }
+                let last = tgs.len().saturating_sub(1);
+                for (index, tg) in tgs.into_iter().enumerate() {
+                    self.pack_group(tg);
+                    if index != last {
+                        self.flush();
+                    }
+                }
+            }
+            Err(err) => {
+                self.is_done = true;
+                self.pending.push_back(Err(err));
+            }
+        }
+    }
+
+    fn pack_group(&mut self, tg: TokenGroup<'a>) {
+        // We'd like to get off without involving subdividing token groups itself.
+        // If we can open a new chunk, we do it. If the token group is larger than max_chunk_size,
+        // only then do we call subdivide on it.
+        if self.chunk.len + tg.len <= self.options.max_chunk_size
+            && self.fits_tags(&[&self.chunk, &tg])
+        {
+            self.push_to_chunk(tg);
+            return;
+        }
+
+        if tg.len <= self.options.max_chunk_size && self.fits_tags(&[&tg]) {
+            self.flush();
+            self.pending.push_back(Ok(tg));
+            return;
+        }
+
+        self.flush();
+
+        let fits_hard_limit = tg.len <= self.options.hard_limit() && self.fits_tags(&[&tg]);
+        if fits_hard_limit && self.has_atomic_element(&tg) {
+            self.pending.push_back(Ok(tg));
+            return;
+        }
+
+        match tg.subdivide_with(&self.options) {
+            // cutting the group would strand a short piece, so it's kept whole past the soft limit
+            Ok(tgs)
+                if fits_hard_limit
+                    && tgs.len() > 1
+                    && tgs
+                        .last()
+                        .is_some_and(|last| last.len < self.options.min_chunk_size) =>
+            {
+                self.pending.push_back(Ok(tg));
+            }
+            Ok(tgs) if self.is_packed_from_end() => {
+                self.pending.extend(tgs.into_iter().rev().map(Ok));
+            }
+            Ok(tgs) => {
+                self.pending.extend(tgs.into_iter().map(Ok));
+            }
+            Err(err @ SplitError::SubdividedExceedingTheLimit(_)) => {
+                self.pending.push_back(Err(err));
+            }
+            Err(err) => {
+                self.is_done = true;
+                self.pending.push_back(Err(err));
+            }
+        }
+    }
+}
+
+impl<'a> SplitIter<'a> {
+    /// The next chunk as a token group, without the overlap
+    pub(crate) fn next_group(&mut self) -> Option<Result<TokenGroup<'a>, SplitError<'a>>> {
+        loop {
+            // the last two chunks are held back until the end, so that a short tail can be balanced
+            let held_back = if self.options.min_chunk_size != 0 && !self.is_done {
+                2
+            } else {
+                0
+            };
+            if self.pending.len() > held_back {
+                let normalize_self_closing = self.options.normalize_self_closing;
+                return self.pending.pop_front().map(|tg| match tg {
+                    Ok(tg) => Ok(tg.normalize_self_closing(normalize_self_closing)),
+                    Err(SplitError::SubdividedExceedingTheLimit(tgs)) => {
+                        Err(SplitError::SubdividedExceedingTheLimit(
+                            tgs.into_iter()
+                                .map(|tg| tg.normalize_self_closing(normalize_self_closing))
+                                .collect(),
+                        ))
+                    }
+                    Err(err) => Err(err),
+                });
+            }
+
+            if self.is_done {
+                return None;
+            }
+
+            if self.is_packed_from_end() {
+                self.pack_from_end();
+                continue;
+            }
+
+            match self.token_groups.next() {
+                Some(Ok(tg)) => self.pack(tg),
+                Some(Err(err)) => {
+                    self.is_done = true;
+                    self.pending.push_back(Err(err));
+                }
+                None => {
+                    self.is_done = true;
+                    if !self.keeps_last_chunk {
+                        self.flush();
+                    }
+                    if self.options.min_chunk_size != 0 {
+                        self.rebalance_tail();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SplitIter<'a> {
+    type Item = Result<String, SplitError<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_group()? {
+                Ok(chunk) => {
+                    // a chunk of whitespace alone is trimmed away
+                    if let Some(chunk) = self.render(&chunk) {
+                        return Some(Ok(chunk));
+                    }
+                }
+                Err(err) => {
+                    self.overlap.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use crate::error::{OwnedSplitError, SplitError};
+use crate::options::SplitOptions;
+use crate::token::Token;
+use crate::tokenizer::{raw_text_tag, Tokenizer};
+
+/// Splits a document that arrives in pieces. The text after the last complete root-level element
+/// is kept until the next [`Splitter::feed`], so the chunks come out the same as if the whole
+/// document was split at once.
+///
+/// ```
+/// use dumb_html_splitter::splitter::Splitter;
+///
+/// let mut splitter = Splitter::new(20, &[]);
+/// let mut chunks = splitter.feed("<b>bold</b> and <i>ita").unwrap();
+/// chunks.extend(splitter.feed("lic</i> text").unwrap());
+/// chunks.extend(splitter.finish().unwrap());
+/// assert_eq!(chunks.concat(), "<b>bold</b> and <i>italic</i> text");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Splitter {
+    options: SplitOptions,
+    buffer: String,
+    /// Where the tokenizer resumes in the buffer; the tokens before it can't change anymore
+    scanned: usize,
+    /// The nesting depth at `scanned`
+    depth: usize,
+    /// The end of the last complete root-level element before `scanned`
+    complete_len: usize,
+}
+
+impl Splitter {
+    pub fn new(max_chunk_size: usize, no_split: &[&str]) -> Self {
+        Self {
+            options: SplitOptions::default()
+                .max_chunk_size(max_chunk_size)
+                .no_split(no_split),
+            buffer: String::new(),
+            scanned: 0,
+            depth: 0,
+            complete_len: 0,
+        }
+    }
+
+    /// Appends `html` to the document and returns the chunks that can't change anymore. Since the
+    /// input is buffered, the errors are owned.
+    pub fn feed(&mut self, html: &str) -> Result<Vec<String>, OwnedSplitError> {
+        self.buffer.push_str(html);
+
+        let previous_len = self.complete_len;
+        self.scan();
+        let end = self.complete_len;
+        if end == previous_len {
+            return Ok(vec![]);
+        }
+
+        let mut iter = self
+            .options
+            .split_iter(&self.buffer[..end])
+            .keep_last_chunk();
+        let mut chunks = vec![];
+        let mut has_exceeded = false;
+
+        while let Some(tg) = iter.next_group() {
+            match tg {
+                Ok(tg) => chunks.extend(iter.render(&tg)),
+                Err(SplitError::SubdividedExceedingTheLimit(exceeding)) => {
+                    has_exceeded = true;
+                    chunks.extend(
+                        exceeding
+                            .iter()
+                            .filter_map(|tg| self.options.finish_chunk(tg.to_string())),
+                    );
+                }
+                Err(err) => return Err(err.into_owned()),
+            }
+        }
+
+        let consumed = iter.unfinished_start().unwrap_or(end);
+        self.buffer.drain(..consumed);
+        self.scanned -= consumed;
+        self.complete_len -= consumed;
+
+        if has_exceeded {
+            return Err(OwnedSplitError::SplitExceededTheLimit(chunks));
+        }
+
+        Ok(chunks)
+    }
+
+    /// Splits whatever is left of the document
+    pub fn finish(mut self) -> Result<Vec<String>, OwnedSplitError> {
+        self.flush()
+    }
+
+    /// Splits whatever is left of the document, and starts over with an empty one
+    pub fn flush(&mut self) -> Result<Vec<String>, OwnedSplitError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.scanned = 0;
+        self.depth = 0;
+        self.complete_len = 0;
+        self.options.split(&buffer).map_err(SplitError::into_owned)
+    }
+
+    /// Tokenizes the buffer from where the previous call stopped, and moves `complete_len` to the
+    /// end of the last root-level element closed by a tag or a comment. A trailing text may still
+    /// continue, and a trailing comment may still be unterminated, so the scan stops before them.
+    /// It also stops before a trailing raw text element, since its text is only read as such
+    /// after the open tag.
+    fn scan(&mut self) {
+        let tokens = Tokenizer::new(&self.buffer[self.scanned..]).collect::<Vec<_>>();
+
+        let mut stable = tokens.len();
+        let mut resume_at = self.buffer.len() - self.scanned;
+        if let Some(token) = tokens.last() {
+            match token {
+                // the text itself doesn't matter here, only a tag that may start in it
+                Token::Text(text, index) => {
+                    stable -= 1;
+                    resume_at = text.find('<').map_or(resume_at, |pos| index + pos);
+                }
+                Token::Comment(comment, index) if !comment.ends_with("-->") => {
+                    stable -= 1;
+                    resume_at = *index;
+                }
+                Token::Declaration(declaration, index)
+                    if declaration.starts_with("<![CDATA[") && !declaration.ends_with("]]>") =>
+                {
+                    stable -= 1;
+                    resume_at = *index;
+                }
+                _ => {}
+            }
+        }
+        if let Some(Token::OpenTag(tag, index)) = stable.checked_sub(1).map(|index| &tokens[index])
+        {
+            if raw_text_tag(tag).is_some() {
+                stable -= 1;
+                resume_at = *index;
+            }
+        }
+
+        for token in &tokens[..stable] {
+            match token {
+                Token::OpenTag(_, _) if token.is_open_in(&self.options.void_tags) => {
+                    self.depth += 1
+                }
+                Token::CloseTag(_, _) => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+
+            let is_complete = match token {
+                Token::CloseTag(_, _) | Token::Declaration(_, _) => true,
+                Token::Comment(comment, _) => comment.ends_with("-->"),
+                _ => false,
+            };
+            if self.depth == 0 && is_complete {
+                self.complete_len = self.scanned + token.index() + token.len();
+            }
+        }
+
+        self.scanned += resume_at;
+    }
+}
+
+/// A [`std::fmt::Write`] sink that splits whatever is written into it. A tag may be written in
+/// several pieces.
+///
+/// ```
+/// use dumb_html_splitter::splitter::ChunkWriter;
+/// use std::fmt::Write;
+///
+/// let mut writer = ChunkWriter::new(20, &[]);
+/// for word in ["one", "two", "three"] {
+///     write!(writer, "<b>{word}</b> ").unwrap();
+/// }
+/// assert_eq!(writer.chunks(), ["<b>one</b> ", "<b>two</b>"]);
+/// assert_eq!(
+///     writer.flush().unwrap(),
+///     ["<b>one</b> ", "<b>two</b>", " <b>three</b> "]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChunkWriter {
+    splitter: Splitter,
+    chunks: Vec<String>,
+    error: Option<OwnedSplitError>,
+}
+
+impl ChunkWriter {
+    pub fn new(max_chunk_size: usize, no_split: &[&str]) -> Self {
+        Self {
+            splitter: Splitter::new(max_chunk_size, no_split),
+            chunks: vec![],
+            error: None,
+        }
+    }
+
+    /// The chunks completed so far
+    pub fn chunks(&self) -> &[String] {
+        &self.chunks
+    }
+
+    /// All the chunks of what has been written, including the incomplete tail. Reports the
+    /// error that failed a write, if any. The writer starts over afterwards.
+    pub fn flush(&mut self) -> Result<Vec<String>, OwnedSplitError> {
+        let mut chunks = std::mem::take(&mut self.chunks);
+        let tail = self.splitter.flush();
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        chunks.extend(tail?);
+        Ok(chunks)
+    }
+}
+
+impl std::fmt::Write for ChunkWriter {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if self.error.is_some() {
+            return Err(std::fmt::Error);
+        }
+
+        match self.splitter.feed(s) {
+            Ok(chunks) => {
+                self.chunks.extend(chunks);
+                Ok(())
+            }
+            Err(err) => {
+                self.error = Some(err);
+                Err(std::fmt::Error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split;
+    use crate::test_data::LONG_HTML;
+    use testresult::TestResult;
+
+    #[test]
+    fn test_feed_in_halves() -> TestResult {
+        for chunk_size in [100, 200, 500, 1000, 4096] {
+            let expected = split(LONG_HTML, chunk_size, &["a"])?;
+
+            for half in [LONG_HTML.len() / 3, LONG_HTML.len() / 2] {
+                let half = (0..=half)
+                    .rev()
+                    .find(|&index| LONG_HTML.is_char_boundary(index))
+                    .unwrap_or_default();
+                let mut splitter = Splitter::new(chunk_size, &["a"]);
+                let mut chunks = splitter.feed(&LONG_HTML[..half])?;
+                chunks.extend(splitter.feed(&LONG_HTML[half..])?);
+                chunks.extend(splitter.finish()?);
+
+                assert_eq!(chunks, expected, "{chunk_size} {half}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_feed_in_small_pieces() -> TestResult {
+        let sample = include_str!("test_data/sample1.html");
+        let wrapped = format!("<div>{sample}</div>");
+        let scripted = "<p>a</p><script>if (a </scr) {}</script><!-- x --><p>b</p> 1 < 2";
+        for html in [LONG_HTML, sample, &wrapped, scripted] {
+            for piece_len in [1, 7, 64] {
+                let expected = split(html, 200, &[])?;
+
+                let mut splitter = Splitter::new(200, &[]);
+                let mut chunks = vec![];
+                let mut start = 0;
+                while start < html.len() {
+                    let mut end = (start + piece_len).min(html.len());
+                    while !html.is_char_boundary(end) {
+                        end += 1;
+                    }
+                    chunks.extend(splitter.feed(&html[start..end])?);
+                    start = end;
+                }
+                chunks.extend(splitter.finish()?);
+
+                assert_eq!(chunks, expected, "{piece_len}");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_feed_keeps_incomplete_elements() -> TestResult {
+        let mut splitter = Splitter::new(24, &[]);
+        assert_eq!(
+            splitter.feed("<b>one</b><b>two</b><i>thr")?,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            splitter.feed("ee</i> tail")?,
+            vec!["<b>one</b><b>two</b>", "<i>three</i>"]
+        );
+        assert_eq!(splitter.finish()?, vec![" tail"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_writer() -> TestResult {
+        use std::fmt::Write;
+
+        let mut writer = ChunkWriter::new(40, &[]);
+        let mut written = String::new();
+        for index in 0..20 {
+            let fragment = format!("<p>paragraph <b>{index}</b> text</p>");
+            // every tag gets split between two writes
+            for piece in fragment.split_inclusive(['<', '>']) {
+                writer.write_str(piece)?;
+            }
+            written.push_str(&fragment);
+        }
+
+        let chunks = writer.flush()?;
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 40), "{chunks:?}");
+        assert_eq!(chunks.concat(), written);
+        assert!(writer.flush()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_writer_error() {
+        use std::fmt::Write;
+
+        let mut writer = ChunkWriter::new(40, &[]);
+        assert!(write!(writer, "<b>unbalanced</i>").is_err());
+        assert!(write!(writer, "<b>more</b>").is_err());
+        assert_eq!(
+            writer.flush(),
+            Err(OwnedSplitError::UnbalancedToken("</i>".into()))
+        );
+    }
+}
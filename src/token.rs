@@ -1,10 +1,45 @@
+use crate::length_mode::LengthMode;
 use std::fmt::{Display, Formatter};
 
+/// The elements that never have a close tag, see [`crate::options::SplitOptions::void_tags`]
+pub const DEFAULT_VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Token<'a> {
     OpenTag(&'a str, usize),
     CloseTag(&'a str, usize),
     Text(&'a str, usize),
+    Comment(&'a str, usize),
+    /// A doctype, an XML declaration, a processing instruction or a CDATA section, like
+    /// `<!DOCTYPE html>`, `<?xml version="1.0"?>` or `<![CDATA[a > b]]>`; it never has a close tag
+    Declaration(&'a str, usize),
+}
+
+/// Same as [`Token`], but owns the text, so it can outlive the source or be deserialized
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OwnedToken {
+    OpenTag(String, usize),
+    CloseTag(String, usize),
+    Text(String, usize),
+    Comment(String, usize),
+    Declaration(String, usize),
+}
+
+impl OwnedToken {
+    pub fn as_token(&self) -> Token<'_> {
+        match self {
+            OwnedToken::OpenTag(text, index) => Token::OpenTag(text, *index),
+            OwnedToken::CloseTag(text, index) => Token::CloseTag(text, *index),
+            OwnedToken::Text(text, index) => Token::Text(text, *index),
+            OwnedToken::Comment(text, index) => Token::Comment(text, *index),
+            OwnedToken::Declaration(text, index) => Token::Declaration(text, *index),
+        }
+    }
 }
 
 impl<'a> Display for Token<'a> {
@@ -14,16 +49,27 @@ impl<'a> Display for Token<'a> {
 }
 
 impl<'a> Token<'a> {
-    pub(crate) fn as_text(&self) -> &'a str {
+    pub fn into_owned(self) -> OwnedToken {
         match self {
-            Token::OpenTag(text, _) | Token::CloseTag(text, _) | Token::Text(text, _) => text,
+            Token::OpenTag(text, index) => OwnedToken::OpenTag(text.to_string(), index),
+            Token::CloseTag(text, index) => OwnedToken::CloseTag(text.to_string(), index),
+            Token::Text(text, index) => OwnedToken::Text(text.to_string(), index),
+            Token::Comment(text, index) => OwnedToken::Comment(text.to_string(), index),
+            Token::Declaration(text, index) => OwnedToken::Declaration(text.to_string(), index),
         }
     }
 
-    pub(crate) fn len_since(&self, start: &Self) -> usize {
-        self.index() + self.len() - start.index()
+    pub fn as_text(&self) -> &'a str {
+        match self {
+            Token::OpenTag(text, _)
+            | Token::CloseTag(text, _)
+            | Token::Text(text, _)
+            | Token::Comment(text, _)
+            | Token::Declaration(text, _) => text,
+        }
     }
-    pub(crate) fn tag_name(&self) -> &str {
+
+    pub fn tag_name(&self) -> &'a str {
         match self {
             Token::OpenTag(text, _) | Token::CloseTag(text, _) => text
                 .trim()
@@ -33,26 +79,157 @@ impl<'a> Token<'a> {
                 .trim_start_matches('/')
                 .split_whitespace()
                 .next()
-                .unwrap(),
+                .unwrap_or(""),
             _ => "",
         }
     }
 
-    pub(crate) fn is_open(&self) -> bool {
+    /// The tag name without the namespace prefix, like `rect` for `<svg:rect>`
+    pub fn local_tag_name(&self) -> &str {
+        let tag_name = self.tag_name();
+        tag_name
+            .rsplit_once(':')
+            .map_or(tag_name, |(_, local)| local)
+    }
+
+    /// The attributes of a tag as `(name, value)` pairs, in the order they appear; a boolean
+    /// attribute like `disabled` has no value
+    pub fn attributes(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        let mut rest = match self {
+            Token::OpenTag(text, _) | Token::CloseTag(text, _) => {
+                let inner = text.trim_start_matches('<').trim_end_matches('>');
+                let inner = inner.trim_start().trim_start_matches('/');
+                inner
+                    .find(|ch: char| ch.is_whitespace() || ch == '/')
+                    .map_or("", |pos| &inner[pos..])
+            }
+            _ => "",
+        };
+
+        std::iter::from_fn(move || {
+            rest = rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '/');
+            if rest.is_empty() {
+                return None;
+            }
+
+            let name_end = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '=' || ch == '/')
+                .unwrap_or(rest.len());
+            let name = &rest[..name_end];
+            rest = rest[name_end..].trim_start();
+
+            let Some(value) = rest.strip_prefix('=') else {
+                return Some((name, None));
+            };
+            let value = value.trim_start();
+
+            let (value, tail) = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let value = &value[1..];
+                    let end = value.find(quote).unwrap_or(value.len());
+                    (&value[..end], value.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                    (&value[..end], &value[end..])
+                }
+            };
+            rest = tail;
+            Some((name, Some(value)))
+        })
+    }
+
+    /// Whether this is an open tag that expects a close one, i.e. neither a void element nor a
+    /// self-closing tag
+    pub fn is_open(&self) -> bool {
+        self.is_open_in(DEFAULT_VOID_TAGS)
+    }
+
+    /// Same as [`Self::is_open`], but with `void_tags` as the void elements
+    pub fn is_open_in<S: AsRef<str>>(&self, void_tags: &[S]) -> bool {
         matches!(self, Token::OpenTag(_, _))
+            && !self.is_void_in(void_tags)
+            && !self.is_self_closing()
     }
 
-    pub(crate) fn is_close(&self) -> bool {
+    /// Whether this is an XHTML-style self-closing tag like `<b/>` or `<img src=x />`. The slash
+    /// ending an unquoted value, like in `<a href=http://x.com/>`, is a part of the value.
+    pub fn is_self_closing(&self) -> bool {
+        let Token::OpenTag(text, _) = self else {
+            return false;
+        };
+        let Some(inner) = text.trim_end_matches('>').trim_end().strip_suffix('/') else {
+            return false;
+        };
+        let last = inner.rsplit(char::is_whitespace).next().unwrap_or_default();
+        !last.contains('=') || last.ends_with(['"', '\''])
+    }
+
+    /// Whether this is a void element like `<br>` or `<img src=x>`, which never has a close tag
+    pub fn is_void(&self) -> bool {
+        self.is_void_in(DEFAULT_VOID_TAGS)
+    }
+
+    /// Same as [`Self::is_void`], but the void elements are the `void_tags`, compared ignoring the
+    /// ASCII case
+    pub fn is_void_in<S: AsRef<str>>(&self, void_tags: &[S]) -> bool {
+        let Token::OpenTag(text, _) = self else {
+            return false;
+        };
+        let name = text[1..]
+            .trim_start()
+            .split(|ch: char| ch.is_whitespace() || ch == '/' || ch == '>')
+            .next()
+            .unwrap_or_default();
+        void_tags
+            .iter()
+            .any(|tag| tag.as_ref().eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_close(&self) -> bool {
         matches!(self, Token::CloseTag(_, _))
     }
 
-    pub(crate) fn len(&self) -> usize {
+    /// The length in bytes
+    pub fn len(&self) -> usize {
         self.as_text().len()
     }
 
-    pub(crate) fn index(&self) -> usize {
+    pub fn is_empty(&self) -> bool {
+        self.as_text().is_empty()
+    }
+
+    pub fn len_in(&self, mode: LengthMode) -> usize {
+        match (mode, self) {
+            (LengthMode::VisibleText, Token::Text(text, _)) => mode.text_len(text),
+            (LengthMode::VisibleText, _) => 0,
+            _ => mode.measure(self.as_text()),
+        }
+    }
+
+    /// Whether this is a close tag for `open`, an open tag taken from the stack. The tag names are
+    /// compared ignoring the ASCII case and the whitespace, so `<div>` is closed by `< / DIV >`
+    pub(crate) fn closes(&self, open: &Token) -> bool {
+        self.is_close()
+            && matches!(open, Token::OpenTag(_, _))
+            && self.tag_name().eq_ignore_ascii_case(open.tag_name())
+    }
+
+    /// Whether both tokens come from the same place of the source, like a tag and its reopened
+    /// copy
+    pub(crate) fn is_same_source(&self, other: &Token) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+            && self.index() == other.index()
+    }
+
+    /// The byte offset of the token in the source document
+    pub fn index(&self) -> usize {
         match self {
-            Token::OpenTag(_, index) | Token::CloseTag(_, index) | Token::Text(_, index) => *index,
+            Token::OpenTag(_, index)
+            | Token::CloseTag(_, index)
+            | Token::Text(_, index)
+            | Token::Comment(_, index)
+            | Token::Declaration(_, index) => *index,
         }
     }
 }
@@ -86,10 +263,25 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn test_is_self_closing() {
+        assert!(Token::OpenTag("<b/>", 0).is_self_closing());
+        assert!(Token::OpenTag("<img src='x.png' />", 0).is_self_closing());
+        assert!(!Token::OpenTag("<b/>", 0).is_open());
+        assert!(!Token::OpenTag("<br>", 0).is_self_closing());
+        assert!(!Token::CloseTag("</b>", 0).is_self_closing());
+
+        assert!(Token::OpenTag("<br/>", 0).is_self_closing());
+        assert!(Token::OpenTag("<input disabled/>", 0).is_self_closing());
+        assert!(Token::OpenTag("<img src='a b.png'/>", 0).is_self_closing());
+        assert!(!Token::OpenTag("<a href=http://x.com/>", 0).is_self_closing());
+        assert!(Token::OpenTag("<a href=http://x.com/>", 0).is_open());
+    }
+
+    #[test]
     fn test_tag_name_empty_tag() {
         let token = Token::OpenTag("<>", 0);
         assert_eq!(token.tag_name(), "");
+        assert_eq!(Token::CloseTag("</ >", 0).tag_name(), "");
     }
 
     #[test]
@@ -99,15 +291,143 @@ mod tests {
     }
 
     #[test]
-    fn test_get_len_from_same_token() {
-        let token = Token::Text("Hello", 0);
-        assert_eq!(token.len_since(&token), token.len());
+    fn test_local_tag_name() {
+        assert_eq!(
+            Token::OpenTag("<svg:rect x='1'>", 0).local_tag_name(),
+            "rect"
+        );
+        assert_eq!(Token::CloseTag("</svg:rect>", 0).tag_name(), "svg:rect");
+        assert_eq!(Token::CloseTag("</svg:rect>", 0).local_tag_name(), "rect");
+        assert_eq!(Token::OpenTag("<rect>", 0).local_tag_name(), "rect");
+    }
+
+    #[test]
+    fn test_attributes() {
+        let token = Token::OpenTag(
+            r#"<span class="tg-spoiler big" data-id='1 2' hidden title=plain/>"#,
+            0,
+        );
+        assert_eq!(
+            token.attributes().collect::<Vec<_>>(),
+            vec![
+                ("class", Some("tg-spoiler big")),
+                ("data-id", Some("1 2")),
+                ("hidden", None),
+                ("title", Some("plain/")),
+            ]
+        );
+
+        let token = Token::OpenTag(r#"<a href="x" target=_blank disabled>"#, 0);
+        assert_eq!(
+            token.attributes().collect::<Vec<_>>(),
+            vec![
+                ("href", Some("x")),
+                ("target", Some("_blank")),
+                ("disabled", None)
+            ]
+        );
+        assert_eq!(
+            Token::OpenTag("<input value='' checked>", 0)
+                .attributes()
+                .collect::<Vec<_>>(),
+            vec![("value", Some("")), ("checked", None)]
+        );
+
+        assert_eq!(
+            Token::OpenTag("<input type=text disabled value='a b'>", 0)
+                .attributes()
+                .collect::<Vec<_>>(),
+            vec![
+                ("type", Some("text")),
+                ("disabled", None),
+                ("value", Some("a b"))
+            ]
+        );
+        assert_eq!(
+            Token::OpenTag(r#"<a href=http://x.com/y title="a 'b'" data-x=it's>"#, 0)
+                .attributes()
+                .collect::<Vec<_>>(),
+            vec![
+                ("href", Some("http://x.com/y")),
+                ("title", Some("a 'b'")),
+                ("data-x", Some("it's"))
+            ]
+        );
+
+        assert_eq!(Token::OpenTag("<b>", 0).attributes().count(), 0);
+        assert_eq!(Token::Text("a=b", 0).attributes().count(), 0);
+    }
+
+    #[test]
+    fn test_is_void() {
+        assert!(Token::OpenTag("<br>", 0).is_void());
+        assert!(Token::OpenTag("<BR/>", 0).is_void());
+        assert!(Token::OpenTag("<img src=x>", 0).is_void());
+        assert!(!Token::OpenTag("<img src=x>", 0).is_open());
+        assert!(!Token::OpenTag("<b>", 0).is_void());
+        assert!(!Token::OpenTag("<>", 0).is_void());
+        assert!(!Token::CloseTag("</br>", 0).is_void());
+
+        let void_tags = ["x-icon"];
+        assert!(Token::OpenTag("<X-Icon name=star>", 0).is_void_in(&void_tags));
+        assert!(!Token::OpenTag("<br>", 0).is_void_in(&void_tags));
+        assert!(Token::OpenTag("<br>", 0).is_open_in(&void_tags));
     }
 
     #[test]
-    fn test_get_len_from_different_tokens() {
-        let start_token = Token::Text("Hello", 10);
-        let end_token = Token::Text("World", 100);
-        assert_eq!(end_token.len_since(&start_token), 95);
+    fn test_closes() {
+        let open = Token::OpenTag("<div class='x'>", 0);
+        assert!(Token::CloseTag("</div>", 0).closes(&open));
+        assert!(Token::CloseTag("< / div >", 0).closes(&open));
+        assert!(Token::CloseTag("</DIV\n>", 0).closes(&open));
+        assert!(!Token::CloseTag("</span>", 0).closes(&open));
+        assert!(!Token::OpenTag("<div>", 0).closes(&open));
+    }
+
+    #[test]
+    fn test_len_in() {
+        let token = Token::Text("👍👍", 0);
+        assert_eq!(token.len(), 8);
+        assert_eq!(token.len_in(LengthMode::Utf8), 8);
+        assert_eq!(token.len_in(LengthMode::Utf16), 4);
+        assert_eq!(token.len_in(LengthMode::Chars), 2);
+        assert_eq!(token.len_in(LengthMode::VisibleText), 2);
+        assert_eq!(Token::OpenTag("<b>", 0).len_in(LengthMode::VisibleText), 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() -> testresult::TestResult {
+        use crate::test_data::SHORT_HTML;
+        use crate::tokenizer::Tokenizer;
+
+        let tokens = Tokenizer::new(SHORT_HTML).collect::<Vec<_>>();
+        let json = serde_json::to_string(&tokens)?;
+        let owned: Vec<OwnedToken> = serde_json::from_str(&json)?;
+        assert_eq!(
+            owned.iter().map(OwnedToken::as_token).collect::<Vec<_>>(),
+            tokens
+        );
+        assert_eq!(
+            owned,
+            tokens
+                .into_iter()
+                .map(Token::into_owned)
+                .collect::<Vec<_>>()
+        );
+
+        let tg = crate::token_group::TokenGroup::from_string("<b>x</b>");
+        assert_eq!(
+            serde_json::to_string(&tg)?,
+            r#"{"tokens":[{"OpenTag":["<b>",0]},{"Text":["x",3]},{"CloseTag":["</b>",4]}],"len":8,"length_mode":"Utf8"}"#
+        );
+
+        let tg = crate::token_group::TokenGroup::from_string(SHORT_HTML);
+        let owned: crate::token_group::OwnedTokenGroup =
+            serde_json::from_str(&serde_json::to_string(&tg)?)?;
+        assert_eq!(owned.as_token_group().tokens, tg.tokens);
+        assert_eq!(owned.as_token_group().len, tg.len);
+        assert_eq!(owned.to_string(), SHORT_HTML);
+        Ok(())
     }
 }
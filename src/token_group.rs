@@ -1,115 +1,826 @@
 use crate::error::SplitError;
-use crate::ext::SplitPosExt;
-use crate::token::Token;
+use crate::ext::{is_breaking_space, SplitPosExt};
+use crate::length_mode::LengthMode;
+use crate::options::{ReopenTag, SplitOptions, WordBreak};
+use crate::token::{OwnedToken, Token};
 use crate::tokenizer::Tokenizer;
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt::{Display, Formatter};
 use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Debug, Default)]
+/// The elements whose text is split at line boundaries, see [`SplitPosExt::split_at_newline`]
+pub(crate) const PREFORMATTED_TAGS: &[&str] = &["pre", "code"];
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct TokenGroup<'a> {
     pub tokens: Vec<Token<'a>>,
+    /// The length of the group measured in `length_mode` units
     pub len: usize,
+    length_mode: LengthMode,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    keeps_empty_tags: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    normalizes_self_closing: bool,
+    /// The void elements, [`DEFAULT_VOID_TAGS`](crate::token::DEFAULT_VOID_TAGS) if not set
+    #[cfg_attr(feature = "serde", serde(skip))]
+    void_tags: Option<Arc<[String]>>,
+    /// The empty elements of the source dropped on push, along with the position of the token
+    /// they were in front of, see [`Self::to_string_lossless`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dropped_empty_tags: Vec<(usize, String)>,
+    /// The [`SplitOptions::continuation_markers`] rendered before and after the tokens. They are
+    /// not in the source, so they are kept out of `tokens`, but counted in `len`. Boxed, since
+    /// few groups have them, to keep the errors holding a group small.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    markers: Option<Box<(&'static str, &'static str)>>,
+}
+
+/// The tags open at a position of subdivision along with their close tags and the reopened form,
+/// so that starting a new group doesn't go through the whole stack again
+#[derive(Default)]
+struct OpenTags<'a> {
+    tokens: Vec<Token<'a>>,
+    closes: Vec<Token<'a>>,
+    /// The tags as they are reopened, an empty one means the tag isn't reopened
+    reopened: Vec<Token<'a>>,
+    reopened_len: usize,
+    /// The length of the open tags as they are in the source
+    len: usize,
+}
+
+impl<'a> OpenTags<'a> {
+    fn push(
+        &mut self,
+        token: Token<'a>,
+        close: Token<'a>,
+        reopen: Option<&ReopenTag>,
+        length_mode: LengthMode,
+    ) {
+        let reopened = reopen
+            .and_then(|reopen| reopen(&token))
+            .map_or(token, |text| Token::OpenTag(text, token.index()));
+        if !reopened.is_empty() {
+            self.reopened_len += reopened.len_in(length_mode);
+        }
+        self.reopened.push(reopened);
+        self.len += token.len_in(length_mode);
+        self.tokens.push(token);
+        self.closes.push(close);
+    }
+
+    /// The names of the open tags, outermost first
+    fn tag_names(&self) -> Vec<&'a str> {
+        self.tokens.iter().map(Token::tag_name).collect()
+    }
+
+    fn pop(&mut self, length_mode: LengthMode) -> Option<Token<'a>> {
+        let token = self.tokens.pop()?;
+        self.closes.pop();
+        let reopened = self.reopened.pop()?;
+        if !reopened.is_empty() {
+            self.reopened_len -= reopened.len_in(length_mode);
+        }
+        self.len -= token.len_in(length_mode);
+        Some(token)
+    }
 }
 
 /// Root-level group of tokens
 impl<'a> TokenGroup<'a> {
-    pub(crate) fn push(&mut self, token: Token<'a>) {
-        debug_assert!(token.len() != 0, "{token:?} has invalid length");
+    pub(crate) fn new(length_mode: LengthMode) -> Self {
+        Self {
+            tokens: vec![],
+            len: 0,
+            length_mode,
+            keeps_empty_tags: false,
+            normalizes_self_closing: false,
+            void_tags: None,
+            dropped_empty_tags: vec![],
+            markers: None,
+        }
+    }
+
+    /// Keeps the empty elements of the source instead of dropping them on push; the tags reopened
+    /// and closed right away are dropped either way
+    pub(crate) fn keep_empty_tags(mut self, keep_empty_tags: bool) -> Self {
+        self.keeps_empty_tags = keep_empty_tags;
+        self
+    }
+
+    /// Renders the self-closing tags as `<tag></tag>`, or as `<tag>` for the void elements. The
+    /// length is still measured on the tags as they are in the source.
+    pub(crate) fn normalize_self_closing(mut self, normalize_self_closing: bool) -> Self {
+        self.normalizes_self_closing = normalize_self_closing;
+        self
+    }
+
+    /// Treats the `void_tags` as the elements that never have a close tag
+    pub(crate) fn void_tags(mut self, void_tags: Arc<[String]>) -> Self {
+        self.void_tags = Some(void_tags);
+        self
+    }
+
+    /// A new empty group with the same settings
+    fn empty(&self) -> Self {
+        let mut tg = Self::new(self.length_mode)
+            .keep_empty_tags(self.keeps_empty_tags)
+            .normalize_self_closing(self.normalizes_self_closing);
+        tg.void_tags = self.void_tags.clone();
+        tg
+    }
+
+    /// Same as [`Token::is_open`], but with the void elements of the group
+    fn is_open(&self, token: &Token) -> bool {
+        match &self.void_tags {
+            Some(void_tags) => token.is_open_in(void_tags),
+            None => token.is_open(),
+        }
+    }
 
-        let is_empty_tag =
-            token.is_close() && self.tokens.last().map_or(false, |last| last.is_open());
-        if is_empty_tag {
-            self.pop();
+    /// Same as [`Token::is_void`], but with the void elements of the group
+    fn is_void(&self, token: &Token) -> bool {
+        match &self.void_tags {
+            Some(void_tags) => token.is_void_in(void_tags),
+            None => token.is_void(),
+        }
+    }
+
+    /// Re-measures the group in the given mode; subdivision produces groups in the same mode
+    pub fn with_length_mode(mut self, length_mode: LengthMode) -> Self {
+        self.length_mode = length_mode;
+        self.len = self.measure(0..self.tokens.len()) + self.markers_len();
+        self
+    }
+
+    /// The continuation markers rendered before and after the tokens, empty if there are none
+    pub(crate) fn markers(&self) -> (&'static str, &'static str) {
+        self.markers.as_deref().copied().unwrap_or_default()
+    }
+
+    fn markers_len(&self) -> usize {
+        let (start, end) = self.markers();
+        self.length_mode.measure(start) + self.length_mode.measure(end)
+    }
+
+    fn set_markers(&mut self, markers: (&'static str, &'static str)) {
+        self.len -= self.markers_len();
+        self.markers = Some(Box::new(markers)).filter(|markers| **markers != ("", ""));
+        self.len += self.markers_len();
+    }
+
+    pub fn length_mode(&self) -> LengthMode {
+        self.length_mode
+    }
+
+    pub(crate) fn push(&mut self, token: Token<'a>) {
+        // there's nothing to keep, and an empty token would make an empty tag look non-empty
+        if token.is_empty() {
             return;
         }
 
+        let open = self
+            .tokens
+            .last()
+            .filter(|last| self.is_open(last))
+            .copied();
+        if let Some(open) = open.filter(|_| token.is_close()) {
+            // an empty element of the source, not a tag reopened in this group
+            let dropped_inside = self
+                .dropped_empty_tags
+                .iter()
+                .filter(|(position, _)| *position == self.tokens.len())
+                .map(|(_, html)| html.len())
+                .sum::<usize>();
+            let is_source_empty = open.index() + open.len() + dropped_inside == token.index();
+            if !(self.keeps_empty_tags && is_source_empty) {
+                self.pop();
+                if is_source_empty {
+                    self.record_dropped(open, token);
+                }
+                return;
+            }
+        }
+
         self.tokens.push(token);
-        self.len += token.len();
+        self.len += token.len_in(self.length_mode);
+    }
+
+    /// Remembers the dropped empty element along with the ones dropped inside it
+    fn record_dropped(&mut self, open: Token, close: Token) {
+        let position = self.tokens.len();
+        let inner_start = self
+            .dropped_empty_tags
+            .partition_point(|(dropped_position, _)| *dropped_position <= position);
+        let mut html = open.as_text().to_string();
+        for (_, inner) in self.dropped_empty_tags.drain(inner_start..) {
+            html.push_str(&inner);
+        }
+        html.push_str(close.as_text());
+        self.dropped_empty_tags.push((position, html));
     }
 
     pub(crate) fn pop(&mut self) -> Option<Token<'a>> {
         let token = self.tokens.pop()?;
-        self.len -= token.len();
+        self.len -= token.len_in(self.length_mode);
         Some(token)
     }
 
-    fn prepare_open_close_map(&self) -> Result<HashMap<Token<'a>, Token<'a>>, SplitError<'a>> {
+    /// Pairs the open tags with the close ones in a single pass. Returns the close token of every
+    /// open one, and the same by the position in the group.
+    #[allow(clippy::type_complexity)]
+    fn prepare_open_close_map(
+        &self,
+    ) -> Result<(HashMap<Token<'a>, Token<'a>>, HashMap<usize, usize>), SplitError<'a>> {
         let mut map = HashMap::new();
+        let mut close_indices = HashMap::new();
         let mut stack = Vec::new();
 
-        for token in self.tokens.iter().copied() {
+        for (index, token) in self.tokens.iter().copied().enumerate() {
             match token {
-                Token::OpenTag(_, _) => {
-                    stack.push(token);
+                Token::OpenTag(_, _) if self.is_open(&token) => {
+                    stack.push((index, token));
                 }
                 Token::CloseTag(_, _) => {
-                    let open = stack.pop().ok_or(SplitError::UnbalancedToken(token))?;
+                    let (open_index, open) =
+                        stack.pop().ok_or(SplitError::UnbalancedToken(token))?;
+                    if !token.closes(&open) {
+                        return Err(SplitError::UnbalancedToken(token));
+                    }
                     map.entry(open).or_insert(token);
+                    close_indices.insert(open_index, index);
                 }
-                Token::Text(_, _) => {}
+                Token::OpenTag(_, _)
+                | Token::Text(_, _)
+                | Token::Comment(_, _)
+                | Token::Declaration(_, _) => {}
             }
         }
 
-        Ok(map)
+        Ok((map, close_indices))
     }
 
     fn get_close_token_index(
         &self,
         open_token_index: usize,
-        map: &HashMap<Token<'a>, Token<'a>>,
+        close_indices: &HashMap<usize, usize>,
     ) -> Result<usize, SplitError<'a>> {
-        let open_token = self.tokens[open_token_index];
-        let close_token = map[&open_token];
-
-        self.tokens
-            .iter()
+        close_indices
+            .get(&open_token_index)
             .copied()
-            .enumerate()
-            .skip(open_token_index)
-            .find(|(_, tg)| tg == &close_token)
-            .map(|(index, _)| index)
-            .ok_or(SplitError::UnbalancedToken(close_token))
+            .ok_or(SplitError::UnbalancedToken(self.tokens[open_token_index]))
     }
 
-    fn wrap(
-        &self,
-        range: Range<usize>,
-        stack: &[Token<'a>],
-        map: &HashMap<Token<'a>, Token<'a>>,
-    ) -> Self {
-        let mut tg = Self::default();
-        tg.open_from_stack(stack);
+    /// A new group with the tags of the stack reopened and the tokens of `range` in it, left open
+    /// so that more tokens can follow
+    fn wrap(&self, range: Range<usize>, stack: &OpenTags<'a>) -> Self {
+        let mut tg = self.new_from_stack(stack);
         for token in self.tokens[range].iter().copied() {
             tg.push(token);
         }
-        tg.close_from_stack(stack, map);
         tg
     }
 
-    fn close_from_stack(&mut self, stack: &[Token<'a>], map: &HashMap<Token<'a>, Token<'a>>) {
-        for token in stack.iter().map(|token| map[token]).rev() {
+    fn close_from_stack(&mut self, stack: &OpenTags<'a>) {
+        for token in stack.closes.iter().copied().rev() {
             self.push(token);
         }
     }
 
-    fn open_from_stack(&mut self, stack: &[Token<'a>]) {
+    /// Reopens the tags of the stack, in the form given by `reopen` if any. The reopened tokens
+    /// keep the source index of the original ones.
+    fn open_from_stack(&mut self, stack: &[Token<'a>], reopen: Option<&ReopenTag>) {
         for token in stack.iter().copied() {
-            self.push(token);
+            let light = reopen.and_then(|reopen| reopen(&token));
+            self.push(light.map_or(token, |text| Token::OpenTag(text, token.index())));
+        }
+    }
+
+    /// A new group with the tags of the stack reopened; they are reopened once when pushed to the
+    /// stack, so this is a single copy
+    fn new_from_stack(&self, stack: &OpenTags<'a>) -> Self {
+        let mut tg = self.empty();
+        // room for the tags, their close ones, and a piece of text in between
+        tg.tokens.reserve_exact(2 * stack.reopened.len() + 1);
+        tg.tokens
+            .extend(stack.reopened.iter().filter(|token| !token.is_empty()));
+        tg.len = stack.reopened_len;
+        tg
+    }
+
+    fn measure(&self, range: Range<usize>) -> usize {
+        self.tokens[range]
+            .iter()
+            .map(|token| token.len_in(self.length_mode))
+            .sum()
+    }
+
+    /// The smallest `max_chunk_size` the group can be subdivided with: the largest piece that
+    /// can't be cut, like a character, a word with [`WordBreak::Never`] or a `no_split` element,
+    /// along with the tags open around it
+    pub(crate) fn min_chunk_size(&self, options: &SplitOptions) -> Result<usize, SplitError<'a>> {
+        let (map, close_indices) = self.prepare_open_close_map()?;
+        // the length of every open tag along with its close one
+        let mut stack = vec![];
+        let mut open_len = 0;
+        let mut min_chunk_size = 0;
+
+        let mut index = 0;
+        while index < self.tokens.len() {
+            let token = self.tokens[index];
+            let stack_len = if options.reopen_tags { open_len } else { 0 };
+
+            let piece_len = match token {
+                Token::OpenTag(_, _) if self.is_open(&token) && options.is_no_split(&token) => {
+                    let close_token_index = self.get_close_token_index(index, &close_indices)?;
+                    let len = self.measure(index..close_token_index + 1);
+                    index = close_token_index;
+                    len
+                }
+                Token::OpenTag(_, _) if self.is_open(&token) => {
+                    let close_len = map
+                        .get(&token)
+                        .map_or(0, |close| close.len_in(self.length_mode));
+                    stack.push(token.len_in(self.length_mode) + close_len);
+                    open_len += stack.last().unwrap();
+                    0
+                }
+                Token::CloseTag(_, _) => {
+                    open_len -= stack.pop().unwrap_or_default();
+                    // an empty element is a piece of its own
+                    let is_empty_tag = index > 0 && self.is_open(&self.tokens[index - 1]);
+                    if is_empty_tag {
+                        stack_len
+                    } else {
+                        0
+                    }
+                }
+                Token::Text(text, _) => {
+                    let marker_len = options
+                        .cut_marker
+                        .map_or(0, |marker| self.length_mode.measure(marker));
+                    match options.word_break {
+                        // a custom break character stays with the word before it
+                        WordBreak::Never => text
+                            .split_inclusive(|ch| options.is_break_char(ch))
+                            .map(|word| {
+                                self.length_mode
+                                    .measure(word.trim_end_matches(is_breaking_space))
+                            })
+                            .max()
+                            .unwrap_or_default(),
+                        WordBreak::AsLastResort => {
+                            text.max_atom_len(self.length_mode, false) + marker_len
+                        }
+                        WordBreak::AtGraphemes => {
+                            text.max_atom_len(self.length_mode, true) + marker_len
+                        }
+                    }
+                    .max(
+                        options
+                            .atomic_runs(text)
+                            .into_iter()
+                            .map(|run| self.length_mode.measure(&text[run]))
+                            .max()
+                            .unwrap_or_default(),
+                    )
+                }
+                Token::OpenTag(_, _) | Token::Comment(_, _) | Token::Declaration(_, _) => {
+                    token.len_in(self.length_mode)
+                }
+            };
+            if piece_len != 0 {
+                min_chunk_size = min_chunk_size.max(stack_len + piece_len);
+            }
+
+            index += 1;
+        }
+
+        // a group that has to be cut needs room for the continuation markers too
+        let markers_len = options.continuation_markers_len();
+        Ok(min_chunk_size.max((min_chunk_size + markers_len).min(self.len)))
+    }
+
+    fn subdivision_impossible(
+        &self,
+        group: Self,
+        open_tags: Vec<&'a str>,
+        options: &SplitOptions,
+    ) -> SplitError<'a> {
+        SplitError::SubdivisionImpossible {
+            group,
+            open_tags,
+            min_chunk_size: self.min_chunk_size(options).unwrap_or_default(),
+        }
+    }
+
+    /// The longest tail of the group that fits into `max_len` and starts at a word boundary. The
+    /// tags that are open at the cut are reopened if `reopen_tags` is set, so the tail is balanced
+    /// if the group is.
+    pub(crate) fn tail(&self, max_len: usize, reopen_tags: bool) -> Self {
+        // instead of a copy of the stack for every token, the innermost open tag and the length
+        // of the stack; the outer tags are found through the parents of the open ones
+        let mut parents = vec![None; self.tokens.len()];
+        let mut innermost = Vec::with_capacity(self.tokens.len());
+        let mut stack_lens = Vec::with_capacity(self.tokens.len());
+        let mut stack = vec![];
+        let mut stack_len = 0;
+        for (index, token) in self.tokens.iter().enumerate() {
+            innermost.push(stack.last().copied());
+            stack_lens.push(stack_len);
+            match token {
+                Token::OpenTag(_, _) if self.is_open(token) => {
+                    parents[index] = stack.last().copied();
+                    stack.push(index);
+                    stack_len += token.len_in(self.length_mode);
+                }
+                Token::CloseTag(_, _) => {
+                    if let Some(open) = stack.pop() {
+                        stack_len -= self.tokens[open].len_in(self.length_mode);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut cut = None;
+        for index in (0..self.tokens.len()).rev() {
+            let Token::Text(text, text_index) = self.tokens[index] else {
+                continue;
+            };
+
+            let stack_len = if reopen_tags { stack_lens[index] } else { 0 };
+            let Some(available_len) =
+                max_len.checked_sub(stack_len + self.measure(index + 1..self.tokens.len()))
+            else {
+                break;
+            };
+
+            let suffix = text.suffix_with_respect_to_whitespace(available_len, self.length_mode);
+            if suffix.is_empty() {
+                break;
+            }
+
+            cut = Some((index, suffix, text_index + text.len() - suffix.len()));
+            if suffix.len() < text.len() {
+                break;
+            }
+        }
+
+        let Some((index, suffix, suffix_index)) = cut else {
+            return self.empty();
+        };
+
+        let mut tg = self.empty();
+        if reopen_tags {
+            let mut stack = std::iter::successors(innermost[index], |&open| parents[open])
+                .map(|open| self.tokens[open])
+                .collect::<Vec<_>>();
+            stack.reverse();
+            tg.open_from_stack(&stack, None);
+        }
+        tg.push(Token::Text(suffix, suffix_index));
+        for token in self.tokens[index + 1..].iter().copied() {
+            tg.push(token);
+        }
+        tg
+    }
+
+    /// Unlike [`Self::push`], keeps empty tags
+    fn push_literally(&mut self, token: Token<'a>) {
+        if token.is_empty() {
+            return;
+        }
+        self.len += token.len_in(self.length_mode);
+        self.tokens.push(token);
+    }
+
+    /// Appends the tokens of `other`. The group keeps its start marker, and takes the end one of
+    /// `other`, if any.
+    pub(crate) fn append(&mut self, other: TokenGroup<'a>) {
+        let start = if self.tokens.is_empty() {
+            other.markers().0
+        } else {
+            self.markers().0
+        };
+        let end = if other.tokens.is_empty() {
+            self.markers().1
+        } else {
+            other.markers().1
+        };
+        self.set_markers(("", ""));
+        self.len += other.len - other.markers_len();
+        self.tokens.extend(other.tokens);
+        self.set_markers((start, end));
+    }
+
+    /// Appends the tokens of `other`, dropping the tags that subdivision closed at the end of this
+    /// group only to reopen them at the start of `other`. The reopened tags are the very same
+    /// tokens, so the genuine ones are kept. The text split at the seam is joined back using
+    /// `source`, the document the tokens come from, if it's at hand.
+    pub(crate) fn merge(&self, other: &Self, source: &'a str) -> Self {
+        let mut tg = Self {
+            tokens: self.tokens.clone(),
+            len: self.len - self.markers_len(),
+            length_mode: self.length_mode,
+            keeps_empty_tags: self.keeps_empty_tags,
+            normalizes_self_closing: self.normalizes_self_closing,
+            void_tags: self.void_tags.clone(),
+            dropped_empty_tags: self.dropped_empty_tags.clone(),
+            markers: None,
+        };
+        let mut tokens = other.tokens.iter().copied().peekable();
+        while let (Some(Token::CloseTag(_, _)), Some(open @ Token::OpenTag(_, _))) =
+            (tg.tokens.last(), tokens.peek())
+        {
+            if !tg.tokens.iter().any(|token| token.is_same_source(open)) {
+                break;
+            }
+            tg.pop();
+            tokens.next();
+        }
+
+        if let (Some(&Token::Text(head, index)), Some(&Token::Text(tail, tail_index))) =
+            (tg.tokens.last(), tokens.peek())
+        {
+            let joined = source.get(index..tail_index + tail.len());
+            if let Some(joined) = joined.filter(|_| index + head.len() == tail_index) {
+                tg.pop();
+                tokens.next();
+                tg.push(Token::Text(joined, index));
+            }
+        }
+
+        for token in tokens {
+            tg.push(token);
         }
+        tg.set_markers((self.markers().0, other.markers().1));
+        tg
+    }
+
+    /// The byte range of the source spanned by the tokens of this group that belong to it, i.e.
+    /// not counting the tags reopened from `previous` or closed early before `next`
+    pub(crate) fn source_range(
+        &self,
+        previous: Option<&Self>,
+        next: Option<&Self>,
+    ) -> Option<Range<usize>> {
+        let is_copy_of = |tg: Option<&Self>, token: &Token| {
+            tg.is_some_and(|tg| tg.tokens.iter().any(|other| other.is_same_source(token)))
+        };
+
+        self.tokens
+            .iter()
+            .filter(|token| !(self.is_open(token) && is_copy_of(previous, token)))
+            .filter(|token| !(token.is_close() && is_copy_of(next, token)))
+            .map(|token| token.index()..token.index() + token.len())
+            .reduce(|range, token_range| {
+                range.start.min(token_range.start)..range.end.max(token_range.end)
+            })
     }
 
-    fn new_from_stack(stack: &[Token<'a>]) -> Self {
-        let mut tg = TokenGroup::default();
-        tg.open_from_stack(stack);
+    /// The length of the text tokens only, in the group's length mode
+    pub fn text_len(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| matches!(token, Token::Text(_, _)))
+            .map(|token| token.len_in(self.length_mode))
+            .sum()
+    }
+
+    /// The text slices of the group along with their source indices, in order
+    pub fn text_tokens(&self) -> impl Iterator<Item = (&'a str, usize)> + '_ {
+        self.tokens.iter().filter_map(|token| match *token {
+            Token::Text(text, index) => Some((text, index)),
+            _ => None,
+        })
+    }
+
+    /// The text of the group without the tags and the comments
+    pub fn to_plain_text(&self) -> String {
+        self.text_tokens().map(|(text, _)| text).collect()
+    }
+
+    /// The group with the text trimmed from its start to its first visible content, and from its
+    /// last one to its end, see [`SplitOptions::trim_chunks`]. The tags and the preformatted text
+    /// are left as is, and so is the raw text of `<script>` and `<style>`. The text left empty is
+    /// dropped.
+    pub(crate) fn trimmed(&self, options: &SplitOptions) -> Self {
+        let is_trimmable = options.collapsible_text(&self.tokens);
+        let is_content = |index: &usize| match self.tokens[*index] {
+            Token::Text(text, _) => !is_trimmable[*index] || !text.trim_ascii().is_empty(),
+            token @ Token::OpenTag(_, _) => token.is_void_in(&options.void_tags),
+            _ => false,
+        };
+        let first = (0..self.tokens.len())
+            .find(is_content)
+            .unwrap_or(self.tokens.len());
+        let last = (0..self.tokens.len()).rfind(is_content);
+
+        let mut tg = self.empty();
+        let mut dropped = self.dropped_empty_tags.iter().peekable();
+        for (index, token) in self.tokens.iter().enumerate() {
+            while let Some((_, html)) = dropped.next_if(|(position, _)| *position <= index) {
+                tg.dropped_empty_tags.push((tg.tokens.len(), html.clone()));
+            }
+
+            let &Token::Text(mut text, mut text_index) = token else {
+                tg.push_literally(*token);
+                continue;
+            };
+            if is_trimmable[index] && index <= first {
+                let trimmed = text.trim_ascii_start();
+                text_index += text.len() - trimmed.len();
+                text = trimmed;
+            }
+            if is_trimmable[index] && last.is_none_or(|last| index >= last) {
+                text = text.trim_ascii_end();
+            }
+            tg.push_literally(Token::Text(text, text_index));
+        }
+        tg.dropped_empty_tags
+            .extend(dropped.map(|(_, html)| (tg.tokens.len(), html.clone())));
+        tg.set_markers(self.markers());
         tg
     }
 
+    /// The slice of `source` the group renders as, if it's a single span of it: no tag was
+    /// reopened or closed early, and nothing was inserted
+    pub(crate) fn as_source_slice(&self, source: &'a str) -> Option<&'a str> {
+        if self.normalizes_self_closing && self.tokens.iter().any(Token::is_self_closing) {
+            return None;
+        }
+        if self.markers.is_some() {
+            return None;
+        }
+
+        let start = self.tokens.first()?.index();
+        let mut end = start;
+        for token in &self.tokens {
+            if token.index() != end || source.get(end..end + token.len()) != Some(token.as_text()) {
+                return None;
+            }
+            end += token.len();
+        }
+        source.get(start..end)
+    }
+
+    /// The tags of the group that are still open at its end and get reopened at the start of
+    /// `next`, outermost first
+    pub(crate) fn carried_stack(&self, next: &TokenGroup<'a>) -> Vec<Token<'a>> {
+        next.tokens
+            .iter()
+            .take_while(|token| next.is_open(token))
+            .filter(|token| self.tokens.iter().any(|own| own.is_same_source(token)))
+            .copied()
+            .collect()
+    }
+
+    /// The close tags ending the group and the open tags starting `next` that only exist because
+    /// an element was cut between them: the number of tokens of the group before the seam, and
+    /// the number of tokens of `next` in it. A tag closed and opened again counts only if
+    /// `is_same` holds for both, e.g. [`Token::is_same_source`] so that two sibling elements are
+    /// never taken for a seam.
+    pub(crate) fn seam_with(
+        &self,
+        next: &TokenGroup,
+        is_same: impl Fn(&Token, &Token) -> bool,
+    ) -> (usize, usize) {
+        let trailing = self
+            .tokens
+            .iter()
+            .rev()
+            .take_while(|token| token.is_close())
+            .count();
+        let first_trailing = self.tokens.len() - trailing;
+
+        // the open tags closed at the very end of the group, innermost first
+        let mut stack = vec![];
+        let mut closed = vec![];
+        for (index, token) in self.tokens.iter().enumerate() {
+            if self.is_open(token) {
+                stack.push(*token);
+            } else if token.is_close() {
+                let open = stack
+                    .iter()
+                    .rposition(|open| token.closes(open))
+                    .and_then(|position| stack.drain(position..).next());
+                if index >= first_trailing {
+                    closed.push(open);
+                }
+            }
+        }
+
+        let reopened = next
+            .tokens
+            .iter()
+            .take_while(|token| next.is_open(token))
+            .zip(closed.iter().rev())
+            .take_while(|(open, closed)| closed.is_some_and(|closed| is_same(&closed, open)))
+            .count();
+        (self.tokens.len() - reopened, reopened)
+    }
+
+    /// Appends the HTML of the group to `buffer`, same as `to_string` but without allocating a
+    /// new string
+    pub fn write_to(&self, buffer: &mut String) {
+        let (start, end) = self.markers();
+        buffer.push_str(start);
+        self.write_tokens_to(0..self.tokens.len(), buffer);
+        buffer.push_str(end);
+    }
+
+    /// Same as `write_to`, but only for the given range of tokens
+    pub(crate) fn write_tokens_to(&self, range: Range<usize>, buffer: &mut String) {
+        let tokens = &self.tokens[range];
+        buffer.reserve(tokens.iter().map(Token::len).sum());
+        for token in tokens {
+            self.write_token(token, buffer);
+        }
+    }
+
+    /// Same as `to_string`, but along with the empty elements of the source that were dropped on
+    /// push, like `<b></b>` in `<b></b>X`. The tags reopened and closed right away by subdivision
+    /// are not in the source, so they are not rendered.
+    pub fn to_string_lossless(&self) -> String {
+        let mut html = self.markers().0.to_string();
+        let mut dropped = self.dropped_empty_tags.iter().peekable();
+        for (index, token) in self.tokens.iter().enumerate() {
+            while let Some((_, empty)) = dropped.next_if(|(position, _)| *position <= index) {
+                html.push_str(empty);
+            }
+            self.write_token(token, &mut html);
+        }
+        for (_, empty) in dropped {
+            html.push_str(empty);
+        }
+        html.push_str(self.markers().1);
+        html
+    }
+
+    fn write_token(&self, token: &Token, buffer: &mut String) {
+        if !(self.normalizes_self_closing && token.is_self_closing()) {
+            buffer.push_str(token.as_text());
+            return;
+        }
+
+        // a malformed tag like `<<br/>` can't be closed by name, so it's kept as is
+        let open = token.as_text().trim_end_matches('>').trim_end();
+        let name = token.tag_name().trim_end_matches('/');
+        let is_valid_name = name.starts_with(|ch: char| ch.is_ascii_alphabetic())
+            && name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | ':' | '.'));
+        if !is_valid_name || !open[1..].trim_start().starts_with(name) {
+            buffer.push_str(token.as_text());
+            return;
+        }
+
+        buffer.push_str(open.trim_end_matches('/').trim_end());
+        buffer.push('>');
+        if !self.is_void(token) {
+            buffer.push_str("</");
+            buffer.push_str(name);
+            buffer.push('>');
+        }
+    }
+
+    /// The number of tokens in the group: tags, text runs and comments
+    pub fn token_count(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// The number of elements in the group, not counting the void and self-closing ones
+    pub fn tag_count(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| self.is_open(token))
+            .count()
+    }
+
+    /// The names of the tags left open at the end of the group, outermost first. A close tag
+    /// only closes its own tag, so the tags it skips over are still reported, like `i` in
+    /// `<p><i>a</p>`.
+    pub fn open_tags(&self) -> Vec<&'a str> {
+        let mut stack: Vec<&Token<'a>> = vec![];
+        for token in &self.tokens {
+            if self.is_open(token) {
+                stack.push(token);
+            } else if let Some(position) = stack.iter().rposition(|open| token.closes(open)) {
+                stack.remove(position);
+            }
+        }
+        stack.into_iter().map(Token::tag_name).collect()
+    }
+
     fn is_all_open(&self) -> bool {
-        self.tokens.iter().all(Token::is_open)
+        self.tokens.iter().all(|token| self.is_open(token))
     }
 
-    // lifetime mismatch for the FromStr trait
+    // lifetime mismatch for the FromStr trait, see `OwnedTokenGroup`
     #[allow(clippy::should_implement_trait)]
     pub fn from_string(html: &'a str) -> Self {
         let mut tg = Self::default();
@@ -118,6 +829,16 @@ impl<'a> TokenGroup<'a> {
         }
         tg
     }
+
+    /// Same as [`Self::from_string`], but the empty elements are kept, so the group renders as
+    /// `html` exactly
+    pub(crate) fn from_string_literally(html: &'a str) -> Self {
+        let mut tg = Self::default();
+        for token in Tokenizer::new(html) {
+            tg.push_literally(token);
+        }
+        tg
+    }
 }
 
 impl<'a> TokenGroup<'a> {
@@ -126,27 +847,108 @@ impl<'a> TokenGroup<'a> {
         max_chunk_size: usize,
         no_split: &[&str],
     ) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+        let options = SplitOptions::default()
+            .max_chunk_size(max_chunk_size)
+            .no_split(no_split)
+            .length_mode(self.length_mode);
+        self.subdivide_with(&options)
+    }
+
+    /// Subdivides the group using `options`; the lengths are measured in the group's own mode
+    pub(crate) fn subdivide_with(
+        &self,
+        options: &SplitOptions,
+    ) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+        let Some((end, start)) = options.continuation_markers else {
+            return self.subdivide_unmarked(options);
+        };
+
+        let markers_len = options.continuation_markers_len();
+        if markers_len >= options.max_chunk_size {
+            return Err(SplitError::InvalidLen(options.max_chunk_size));
+        }
+        let reduced = options
+            .clone()
+            .max_chunk_size(options.max_chunk_size - markers_len);
+        match self.subdivide_unmarked(&reduced) {
+            Ok(mut tgs) => {
+                self.mark_continuations(&mut tgs, end, start);
+                Ok(tgs)
+            }
+            Err(SplitError::SubdividedExceedingTheLimit(mut tgs)) => {
+                self.mark_continuations(&mut tgs, end, start);
+                Err(SplitError::SubdividedExceedingTheLimit(tgs))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Adds the [`SplitOptions::continuation_markers`] around the seams of `tgs`, the pieces of
+    /// this group, that an element continues through. The pieces at the edges keep the markers of
+    /// the group itself, so a group subdivided again is never marked twice.
+    fn mark_continuations(
+        &self,
+        tgs: &mut [TokenGroup<'a>],
+        end: &'static str,
+        start: &'static str,
+    ) {
+        let seams = tgs
+            .windows(2)
+            .map(|pair| !pair[0].carried_stack(&pair[1]).is_empty())
+            .collect::<Vec<_>>();
+        let last = tgs.len().saturating_sub(1);
+        for (index, tg) in tgs.iter_mut().enumerate() {
+            let continues =
+                index.checked_sub(1).map_or(
+                    self.markers().0,
+                    |seam| if seams[seam] { start } else { "" },
+                );
+            let continued = if index == last {
+                self.markers().1
+            } else if seams[index] {
+                end
+            } else {
+                ""
+            };
+            tg.set_markers((continues, continued));
+        }
+    }
+
+    fn subdivide_unmarked(
+        &self,
+        options: &SplitOptions,
+    ) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+        let max_chunk_size = options.max_chunk_size;
         if max_chunk_size == 0 {
             return Err(SplitError::InvalidLen(max_chunk_size));
         }
 
-        let map = self.prepare_open_close_map()?;
-        let mut stack = vec![];
+        if !options.reopen_tags {
+            return self.subdivide_literally(options);
+        }
+
+        let is_break = |ch| options.is_break_char(ch);
+        let reopen = options.reopen.as_ref();
+        let (map, close_indices) = self.prepare_open_close_map()?;
+        let mut stack = OpenTags::default();
         let mut future_close_len = 0;
         let mut token_groups = vec![];
-        let mut tg = TokenGroup::default();
+        let mut tg = self.empty();
+        let is_out_of_tags = |tg: &Self| {
+            options
+                .max_tags_per_chunk
+                .is_some_and(|max_tags| tg.tag_count() >= max_tags)
+        };
 
         let mut index = 0;
         while index < self.tokens.len() {
             let token = self.tokens[index];
             let close_token = map.get(&token);
-            let close_token_len = close_token.map(|token| token.len());
-
-            let len_till_close = close_token.map(|ct| ct.len_since(&token));
+            let close_token_len = close_token.map(|token| token.len_in(self.length_mode));
 
             match token {
                 // since we haven't opened the tag yet, we are free to stop right here
-                Token::OpenTag(_, _) => {
+                Token::OpenTag(_, _) if self.is_open(&token) => {
                     let close_token = close_token.unwrap();
                     let close_token_len =
                         close_token_len.ok_or(SplitError::UnbalancedToken(*close_token))?;
@@ -155,44 +957,89 @@ impl<'a> TokenGroup<'a> {
                     // In this case, we just immediately open a new token group despite the fact
                     // it still might not fit in max_chunk_size even after subdivision:
                     // we're doing our best, but if a no_split tag is too large, we can't fix it.
-                    if no_split.contains(&token.tag_name())
-                        && tg.len + future_close_len + len_till_close.unwrap() > max_chunk_size
-                    {
-                        let close_token_index = self.get_close_token_index(index, &map)?;
-                        tg.close_from_stack(&stack, &map);
-                        token_groups.push(tg);
-                        tg = self.wrap(index..close_token_index + 1, &stack, &map);
-
-                        // if we see that we are already exceeding the limit,
-                        // recreate the token group
-                        if tg.len + future_close_len >= max_chunk_size {
+                    let is_no_split = options.is_no_split(&token);
+                    if is_no_split || options.is_atomic(&token) {
+                        let close_token_index =
+                            self.get_close_token_index(index, &close_indices)?;
+                        let len_till_close = self.measure(index..close_token_index + 1);
+                        let tags_till_close = self.tokens[index..close_token_index + 1]
+                            .iter()
+                            .filter(|token| self.is_open(token))
+                            .count();
+                        let fits_tags = |tag_count: usize| {
+                            options
+                                .max_tags_per_chunk
+                                .is_none_or(|max_tags| tag_count + tags_till_close <= max_tags)
+                        };
+
+                        // unlike no_split ones, atomic tags are split as usual if they don't fit
+                        // into a chunk on their own
+                        let fits_alone = stack.len + len_till_close + future_close_len
+                            <= max_chunk_size
+                            && fits_tags(stack.tokens.len());
+
+                        if (is_no_split || fits_alone)
+                            && (tg.len + future_close_len + len_till_close > max_chunk_size
+                                || !fits_tags(tg.tag_count()))
+                        {
+                            tg.close_from_stack(&stack);
                             token_groups.push(tg);
-                            tg = Self::new_from_stack(&stack);
-                        }
+                            tg = self.wrap(index..close_token_index + 1, &stack);
+
+                            // if we see that we are already exceeding the limit,
+                            // recreate the token group
+                            if tg.len + future_close_len >= max_chunk_size {
+                                tg.close_from_stack(&stack);
+                                token_groups.push(tg);
+                                tg = self.new_from_stack(&stack);
+                            }
 
-                        // rewind to the position right after the close token
-                        index = close_token_index + 1;
-                        continue;
+                            // rewind to the position right after the close token
+                            index = close_token_index + 1;
+                            continue;
+                        }
                     }
 
                     // Now, we solve the case when we know that there will be not enough space to
-                    // close the currently open tags if we push this one
-                    if tg.len + token.len() + close_token_len + future_close_len >= max_chunk_size {
+                    // close the currently open tags if we push this one, or the group has as many
+                    // tags as allowed already
+                    if is_out_of_tags(&tg)
+                        || tg.len
+                            + token.len_in(self.length_mode)
+                            + close_token_len
+                            + future_close_len
+                            >= max_chunk_size
+                    {
                         // If all tags we added to the current group are open tags, and we've
                         // already run out of space, then there's no point in trying
                         if tg.is_all_open() {
-                            return Err(SplitError::SubdivisionImpossible(tg));
+                            return Err(self.subdivision_impossible(
+                                tg,
+                                stack.tag_names(),
+                                options,
+                            ));
                         }
-                        tg.close_from_stack(&stack, &map);
+                        tg.close_from_stack(&stack);
                         token_groups.push(tg);
                         // we just need to clone the stack
-                        tg = Self::new_from_stack(&stack);
+                        tg = self.new_from_stack(&stack);
+                        if is_out_of_tags(&tg) {
+                            return Err(self.subdivision_impossible(
+                                tg,
+                                stack.tag_names(),
+                                options,
+                            ));
+                        }
                     }
 
                     future_close_len += close_token_len;
                     tg.push(token);
                     debug_assert!(tg.len <= max_chunk_size);
-                    stack.push(token);
+                    stack.push(token, *close_token, reopen, self.length_mode);
+                    let depth = stack.tokens.len();
+                    if options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+                        return Err(SplitError::MaxDepthExceeded(depth));
+                    }
                     index += 1;
                 }
                 // since we have accounted for close tags when we opened them, we should not run
@@ -201,12 +1048,44 @@ impl<'a> TokenGroup<'a> {
                     tg.push(token);
                     debug_assert!(tg.len <= max_chunk_size);
 
-                    future_close_len -= token.len();
-                    stack.pop().ok_or(SplitError::UnbalancedToken(token))?;
+                    future_close_len -= token.len_in(self.length_mode);
+                    stack
+                        .pop(self.length_mode)
+                        .ok_or(SplitError::UnbalancedToken(token))?;
+                    index += 1;
+                }
+                // comments, declarations and void elements are atomic: if one does not fit, it
+                // goes to the next group as a whole
+                Token::Comment(_, _) | Token::Declaration(_, _) | Token::OpenTag(_, _) => {
+                    if tg.len + future_close_len + token.len_in(self.length_mode) > max_chunk_size {
+                        if !tg.is_all_open() {
+                            tg.close_from_stack(&stack);
+                            token_groups.push(tg);
+                            tg = self.new_from_stack(&stack);
+                        }
+
+                        // the comment alone exceeds the limit, so we leave it on its own
+                        tg.push(token);
+                        if tg.len + future_close_len > max_chunk_size {
+                            tg.close_from_stack(&stack);
+                            token_groups.push(tg);
+                            tg = self.new_from_stack(&stack);
+                        }
+                    } else {
+                        tg.push(token);
+                    }
+
+                    let is_last = index + 1 == self.tokens.len();
+                    if options.is_break_after(&token) && !is_last && !tg.is_all_open() {
+                        tg.close_from_stack(&stack);
+                        token_groups.push(tg);
+                        tg = self.new_from_stack(&stack);
+                    }
+
                     index += 1;
                 }
                 Token::Text(mut text, mut text_start_index) => {
-                    let future_len = tg.len + future_close_len + token.len();
+                    let future_len = tg.len + future_close_len + token.len_in(self.length_mode);
                     if future_len <= max_chunk_size {
                         tg.push(token);
                         assert!(tg.len <= max_chunk_size);
@@ -215,44 +1094,191 @@ impl<'a> TokenGroup<'a> {
                         continue;
                     }
 
+                    // a soft break right before the text is a better place to split than the text
+                    if tg
+                        .tokens
+                        .last()
+                        .is_some_and(|last| options.is_soft_break(last))
+                    {
+                        tg.close_from_stack(&stack);
+                        token_groups.push(tg);
+                        tg = self.new_from_stack(&stack);
+                    }
+
                     // Here we split the text till the first whitespace as long as it does not fit,
                     // and progress tag text + index
                     loop {
                         debug_assert!(tg.len <= max_chunk_size);
                         if future_close_len + tg.len > max_chunk_size {
-                            return Err(SplitError::SubdivisionImpossible(tg));
+                            return Err(self.subdivision_impossible(
+                                tg,
+                                stack.tag_names(),
+                                options,
+                            ));
                         }
 
                         let mut available_len = max_chunk_size - future_close_len - tg.len;
                         if available_len == 0 {
-                            tg.close_from_stack(&stack, &map);
+                            tg.close_from_stack(&stack);
                             token_groups.push(tg);
-                            tg = Self::new_from_stack(&stack);
+                            tg = self.new_from_stack(&stack);
                             available_len = max_chunk_size - future_close_len - tg.len;
                             if available_len == 0 {
-                                return Err(SplitError::SubdivisionImpossible(tg));
+                                return Err(self.subdivision_impossible(
+                                    tg,
+                                    stack.tag_names(),
+                                    options,
+                                ));
                             }
                         }
-                        let can_fit_segment = text
-                            .split_with_respect_to_whitespace(available_len)
-                            .ok_or(SplitError::SubdivisionImpossibleUnicode(token))?;
+                        // a URL that does not fit goes to a new group, unless this one is fresh
+                        let moves_url = options.protect_urls
+                            && text.starts_with_url()
+                            && !tg.is_all_open()
+                            && text
+                                .split_at_whitespace(available_len, self.length_mode)
+                                .is_empty();
+                        if moves_url {
+                            tg.close_from_stack(&stack);
+                            token_groups.push(tg);
+                            tg = self.new_from_stack(&stack);
+                            continue;
+                        }
+
+                        // the whitespace of preformatted text matters, so whole lines are kept
+                        // whenever they fit
+                        let is_preformatted = stack.tokens.iter().any(|token| {
+                            PREFORMATTED_TAGS
+                                .iter()
+                                .any(|tag| tag.eq_ignore_ascii_case(token.tag_name()))
+                        });
+                        let preferred = if is_preformatted {
+                            text.split_at_newline(available_len, self.length_mode)
+                        } else if options.sentence_aware {
+                            text.split_at_sentence(available_len, self.length_mode)
+                        } else {
+                            ""
+                        };
+                        let can_fit_segment = match options.word_break {
+                            _ if !preferred.is_empty() => preferred,
+                            WordBreak::AsLastResort => text
+                                .split_with_respect_to(available_len, self.length_mode, is_break)
+                                .ok_or(SplitError::SubdivisionImpossibleUnicode(token))?,
+                            WordBreak::AtGraphemes => text
+                                .split_with_respect_to_graphemes(
+                                    available_len,
+                                    self.length_mode,
+                                    is_break,
+                                )
+                                .ok_or(SplitError::SubdivisionImpossibleUnicode(token))?,
+                            WordBreak::Never => {
+                                let segment =
+                                    text.split_at_break(available_len, self.length_mode, is_break);
+                                if segment.is_empty() {
+                                    // The word does not fit into what's left of the group, so we
+                                    // move it to a new one. Unless this one is fresh already.
+                                    if tg.is_all_open() {
+                                        return Err(self.subdivision_impossible(
+                                            tg,
+                                            stack.tag_names(),
+                                            options,
+                                        ));
+                                    }
+                                    tg.close_from_stack(&stack);
+                                    token_groups.push(tg);
+                                    tg = self.new_from_stack(&stack);
+                                    continue;
+                                }
+                                segment
+                            }
+                        };
+
+                        // the cut is moved before an atomic run it would fall into, and the run
+                        // goes to a new group if it's at the start already
+                        let atomic_run_start = options
+                            .atomic_runs(text)
+                            .into_iter()
+                            .find(|run| {
+                                run.start < can_fit_segment.len() && run.end > can_fit_segment.len()
+                            })
+                            .map(|run| run.start);
+                        let can_fit_segment = match atomic_run_start {
+                            Some(0) if tg.is_all_open() => {
+                                return Err(self.subdivision_impossible(
+                                    tg,
+                                    stack.tag_names(),
+                                    options,
+                                ));
+                            }
+                            Some(0) => {
+                                tg.close_from_stack(&stack);
+                                token_groups.push(tg);
+                                tg = self.new_from_stack(&stack);
+                                continue;
+                            }
+                            Some(start) => &text[..start],
+                            None => can_fit_segment,
+                        };
+
+                        // a word cut in the middle gets the marker, if there's room for it
+                        let is_cut = atomic_run_start.is_none()
+                            && can_fit_segment.len() < text.len()
+                            && !can_fit_segment.ends_with(is_break)
+                            && !text[can_fit_segment.len()..].starts_with(is_break);
+                        let (can_fit_segment, marker) = match options.cut_marker {
+                            Some(marker) if is_cut => {
+                                let max_len =
+                                    available_len.saturating_sub(self.length_mode.measure(marker));
+                                let segment = if options.word_break == WordBreak::AtGraphemes {
+                                    text.split_with_respect_to_graphemes(
+                                        max_len,
+                                        self.length_mode,
+                                        is_break,
+                                    )
+                                } else {
+                                    text.split_with_respect_to(max_len, self.length_mode, is_break)
+                                };
+                                match segment.filter(|segment| !segment.is_empty()) {
+                                    Some(segment) => (segment, Some(marker)),
+                                    None if tg.is_all_open() => {
+                                        return Err(self.subdivision_impossible(
+                                            tg,
+                                            stack.tag_names(),
+                                            options,
+                                        ));
+                                    }
+                                    None => {
+                                        tg.close_from_stack(&stack);
+                                        token_groups.push(tg);
+                                        tg = self.new_from_stack(&stack);
+                                        continue;
+                                    }
+                                }
+                            }
+                            _ => (can_fit_segment, None),
+                        };
 
                         debug_assert!(!can_fit_segment.is_empty(), "{text}");
                         debug_assert!(
-                            can_fit_segment.len() <= available_len,
+                            self.length_mode.measure(can_fit_segment) <= available_len,
                             "`{text}` got split into `{can_fit_segment}`; available_len: {available_len}"
                         );
 
                         tg.push(Token::Text(can_fit_segment, text_start_index));
+                        // the marker is not in the source, so it borrows the position of the
+                        // segment to stay within the range of the group
+                        if let Some(marker) = marker {
+                            tg.push(Token::Text(marker, text_start_index));
+                        }
                         debug_assert!(tg.len <= max_chunk_size);
 
                         text = &text[can_fit_segment.len()..];
                         text_start_index += can_fit_segment.len();
 
                         debug_assert!(!tg.is_all_open());
-                        tg.close_from_stack(&stack, &map);
+                        tg.close_from_stack(&stack);
                         token_groups.push(tg);
-                        tg = Self::new_from_stack(&stack);
+                        tg = self.new_from_stack(&stack);
 
                         if text.is_empty() {
                             break;
@@ -264,8 +1290,8 @@ impl<'a> TokenGroup<'a> {
             }
         }
 
-        if !stack.is_empty() {
-            return Err(SplitError::UnbalancedToken(stack.pop().unwrap()));
+        if let Some(token) = stack.tokens.pop() {
+            return Err(SplitError::UnbalancedToken(token));
         }
 
         debug_assert!(tg.len <= max_chunk_size);
@@ -274,22 +1300,345 @@ impl<'a> TokenGroup<'a> {
         if !tg.tokens.is_empty() && !tg.is_all_open() {
             token_groups.push(tg);
         }
+        // closing a group that only has the reopened tags leaves nothing in it
+        token_groups.retain(|tg| !tg.tokens.is_empty());
 
         // A case when we have no_split tags exceeding the max_chunk_size limit
         for tg in &token_groups {
-            if tg.len > max_chunk_size {
+            if tg.len > max_chunk_size + options.tolerance {
                 return Err(SplitError::SubdividedExceedingTheLimit(token_groups));
             }
         }
         Ok(token_groups)
     }
+
+    /// Subdivides the group into slices of the source, without closing or reopening any tags. The
+    /// cuts are made where no tag is open if possible, otherwise the tags are left unbalanced.
+    fn subdivide_literally(
+        &self,
+        options: &SplitOptions,
+    ) -> Result<Vec<TokenGroup<'a>>, SplitError<'a>> {
+        let max_chunk_size = options.max_chunk_size;
+        let is_break = |ch| options.is_break_char(ch);
+        let (_, close_indices) = self.prepare_open_close_map()?;
+        let mut token_groups = vec![];
+        let mut tg = self.empty();
+        // the number of tokens of `tg` after which no tag is open
+        let mut root_cut = 0;
+        let mut depth = 0usize;
+
+        let mut index = 0;
+        while index < self.tokens.len() {
+            let token = self.tokens[index];
+            let mut end = index + 1;
+            if self.is_open(&token) && (options.is_no_split(&token) || options.is_atomic(&token)) {
+                let close_token_index = self.get_close_token_index(index, &close_indices)?;
+                if options.is_no_split(&token)
+                    || self.measure(index..close_token_index + 1) <= max_chunk_size
+                {
+                    end = close_token_index + 1;
+                }
+            }
+
+            let len = self.measure(index..end);
+            if tg.len + len > max_chunk_size {
+                if let Token::Text(mut text, mut text_index) = token {
+                    loop {
+                        let available_len = max_chunk_size.saturating_sub(tg.len);
+                        let sentence = if options.sentence_aware {
+                            text.split_at_sentence(available_len, self.length_mode)
+                        } else {
+                            ""
+                        };
+                        let segment = match options.word_break {
+                            _ if !sentence.is_empty() => sentence,
+                            _ if available_len == 0 => "",
+                            WordBreak::AsLastResort => text
+                                .split_with_respect_to(available_len, self.length_mode, is_break)
+                                .ok_or(SplitError::SubdivisionImpossibleUnicode(token))?,
+                            WordBreak::AtGraphemes => text
+                                .split_with_respect_to_graphemes(
+                                    available_len,
+                                    self.length_mode,
+                                    is_break,
+                                )
+                                .ok_or(SplitError::SubdivisionImpossibleUnicode(token))?,
+                            WordBreak::Never => {
+                                text.split_at_break(available_len, self.length_mode, is_break)
+                            }
+                        };
+
+                        if segment.is_empty() {
+                            if tg.tokens.is_empty() {
+                                return Err(self.subdivision_impossible(tg, vec![], options));
+                            }
+                            token_groups.push(std::mem::replace(&mut tg, self.empty()));
+                            root_cut = 0;
+                            continue;
+                        }
+
+                        tg.push_literally(Token::Text(segment, text_index));
+                        text = &text[segment.len()..];
+                        text_index += segment.len();
+                        if text.is_empty() {
+                            break;
+                        }
+
+                        token_groups.push(std::mem::replace(&mut tg, self.empty()));
+                        root_cut = 0;
+                    }
+
+                    if depth == 0 {
+                        root_cut = tg.tokens.len();
+                    }
+                    index = end;
+                    continue;
+                }
+
+                // we'd rather move the tokens after the last root-level cut to the next group
+                let cut = if root_cut != 0 {
+                    root_cut
+                } else {
+                    tg.tokens.len()
+                };
+                let mut next = self.empty();
+                for token in tg.tokens.split_off(cut) {
+                    next.push_literally(token);
+                }
+                tg.len -= next.len;
+                if !tg.tokens.is_empty() {
+                    token_groups.push(tg);
+                }
+                tg = next;
+                root_cut = 0;
+            }
+
+            for token in self.tokens[index..end].iter().copied() {
+                match token {
+                    Token::OpenTag(_, _) if self.is_open(&token) => depth += 1,
+                    Token::CloseTag(_, _) => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                if options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+                    return Err(SplitError::MaxDepthExceeded(depth));
+                }
+                tg.push_literally(token);
+            }
+            if depth == 0 {
+                root_cut = tg.tokens.len();
+            }
+            index = end;
+        }
+
+        if !tg.tokens.is_empty() {
+            token_groups.push(tg);
+        }
+
+        if token_groups
+            .iter()
+            .any(|tg| tg.len > max_chunk_size + options.tolerance)
+        {
+            return Err(SplitError::SubdividedExceedingTheLimit(token_groups));
+        }
+        Ok(token_groups)
+    }
+}
+
+/// Lazily groups the tokens of a document into root-level token groups, i.e. groups that close
+/// every tag they open
+pub(crate) struct RootTokenGroups<'a> {
+    tokenizer: Tokenizer<'a>,
+    stack: Vec<Token<'a>>,
+    length_mode: LengthMode,
+    max_depth: Option<usize>,
+    keeps_empty_tags: bool,
+    void_tags: Option<Arc<[String]>>,
+    is_strict: bool,
+    is_done: bool,
+}
+
+impl<'a> RootTokenGroups<'a> {
+    pub(crate) fn new(html: &'a str, length_mode: LengthMode) -> Self {
+        Self {
+            tokenizer: Tokenizer::new(html),
+            stack: vec![],
+            length_mode,
+            max_depth: None,
+            keeps_empty_tags: false,
+            void_tags: None,
+            is_strict: false,
+            is_done: false,
+        }
+    }
+
+    pub(crate) fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub(crate) fn keep_empty_tags(mut self, keep_empty_tags: bool) -> Self {
+        self.keeps_empty_tags = keep_empty_tags;
+        self
+    }
+
+    pub(crate) fn void_tags(mut self, void_tags: Arc<[String]>) -> Self {
+        self.void_tags = Some(void_tags);
+        self
+    }
+
+    /// Fails with [`SplitError::MalformedTag`] instead of keeping the malformed markup as text
+    pub(crate) fn strict(mut self, strict: bool) -> Self {
+        self.is_strict = strict;
+        self
+    }
 }
 
+impl<'a> Iterator for RootTokenGroups<'a> {
+    type Item = Result<TokenGroup<'a>, SplitError<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Since most of the html text this splitter is supposed to split is markdown-like
+        // formatting converted to html, there will be no root element. Most of the tags will be
+        // like `<b>something</b>`, or at worst `Some text <b>something <i>italic</i></b> blah`.
+        // So, instead of trying to stuff the max possible amount of text into a single chunk, we
+        // prefer to put it into the next one. Apart from that, all links, or bold titles, and
+        // other whatnot will be moved to the next chunk if they don't fit. I guess it's better for
+        // messengers where you would not like to read split titles.
+        if self.is_done {
+            return None;
+        }
+
+        let mut token_group =
+            TokenGroup::new(self.length_mode).keep_empty_tags(self.keeps_empty_tags);
+        token_group.void_tags = self.void_tags.clone();
+
+        while let Some(token) = self.tokenizer.next() {
+            if let Some((at, snippet)) = self.tokenizer.malformed().filter(|_| self.is_strict) {
+                self.is_done = true;
+                return Some(Err(SplitError::MalformedTag { at, snippet }));
+            }
+            token_group.push(token);
+
+            match token {
+                Token::OpenTag(_, _) if token_group.is_open(&token) => {
+                    self.stack.push(token);
+                    if self.max_depth.is_some_and(|depth| self.stack.len() > depth) {
+                        self.is_done = true;
+                        return Some(Err(SplitError::MaxDepthExceeded(self.stack.len())));
+                    }
+                }
+                Token::CloseTag(_, _) => {
+                    let is_closed = self.stack.pop().is_some_and(|open| token.closes(&open));
+                    if !is_closed {
+                        self.is_done = true;
+                        return Some(Err(SplitError::UnbalancedToken(token)));
+                    }
+                }
+                _ => {}
+            }
+
+            if self.stack.is_empty() {
+                return Some(Ok(token_group));
+            }
+        }
+
+        self.is_done = true;
+        self.stack
+            .pop()
+            .map(|token| Err(SplitError::UnbalancedToken(token)))
+    }
+}
+
+/// Renders the tokens of the group. An element with nothing inside, like `<b></b>`, is dropped when
+/// it's pushed unless the group keeps the empty tags, so it's never rendered; see
+/// [`TokenGroup::to_string_lossless`].
 impl<'a> Display for TokenGroup<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.normalizes_self_closing {
+            let mut html = String::new();
+            self.write_to(&mut html);
+            return f.write_str(&html);
+        }
+
+        f.write_str(self.markers().0)?;
         for token in &self.tokens {
             write!(f, "{}", token)?;
         }
+        f.write_str(self.markers().1)
+    }
+}
+
+impl<'a> IntoIterator for TokenGroup<'a> {
+    type Item = Token<'a>;
+    type IntoIter = std::vec::IntoIter<Token<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tokens.into_iter()
+    }
+}
+
+/// Iterates over the tokens of the group
+///
+/// ```
+/// use dumb_html_splitter::token_group::TokenGroup;
+///
+/// let tg = TokenGroup::from_string("<b>bold</b> text");
+/// let len = (&tg).into_iter().map(|token| token.len()).sum::<usize>();
+/// assert_eq!(len, tg.len);
+/// assert_eq!(tg.into_iter().count(), 4);
+/// ```
+impl<'a, 'b> IntoIterator for &'b TokenGroup<'a> {
+    type Item = Token<'a>;
+    type IntoIter = std::iter::Copied<std::slice::Iter<'b, Token<'a>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tokens.iter().copied()
+    }
+}
+
+/// Same as [`TokenGroup`], but owns its tokens, so it can be parsed from a string. A serialized
+/// [`TokenGroup`] is deserialized into it, since the group borrows its tokens.
+///
+/// ```
+/// use dumb_html_splitter::token_group::OwnedTokenGroup;
+///
+/// let tg = "<b>bold</b> text".parse::<OwnedTokenGroup>().unwrap();
+/// assert_eq!(tg.tokens.len(), 4);
+/// assert_eq!(tg.as_token_group().text_len(), 9);
+/// assert_eq!(tg.to_string(), "<b>bold</b> text");
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedTokenGroup {
+    pub tokens: Vec<OwnedToken>,
+}
+
+impl OwnedTokenGroup {
+    /// Borrows the tokens as a [`TokenGroup`] measured in the default length mode
+    pub fn as_token_group(&self) -> TokenGroup<'_> {
+        let mut tg = TokenGroup::new(LengthMode::default());
+        tg.tokens = self.tokens.iter().map(OwnedToken::as_token).collect();
+        tg.len = tg.measure(0..tg.tokens.len());
+        tg
+    }
+}
+
+impl FromStr for OwnedTokenGroup {
+    type Err = Infallible;
+
+    fn from_str(html: &str) -> Result<Self, Self::Err> {
+        let tg = TokenGroup::from_string(html);
+        Ok(Self {
+            tokens: tg.tokens.into_iter().map(Token::into_owned).collect(),
+        })
+    }
+}
+
+impl Display for OwnedTokenGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for token in &self.tokens {
+            write!(f, "{}", token.as_token())?;
+        }
         Ok(())
     }
 }
@@ -422,6 +1771,218 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_push_skips_empty_tokens() {
+        let mut tg = TokenGroup::new(LengthMode::Utf8);
+        tg.push(Token::OpenTag("<b>", 0));
+        tg.push(Token::Text("", 3));
+        tg.push(Token::CloseTag("</b>", 3));
+        assert!(tg.tokens.is_empty());
+        assert_eq!(tg.len, 0);
+
+        tg.push(Token::Text("", 0));
+        tg.push(Token::Text("text", 0));
+        assert_eq!(tg.tokens, vec![Token::Text("text", 0)]);
+        assert_eq!(tg.len, 4);
+    }
+
+    #[test]
+    fn test_irregular_close_tags() -> TestResult {
+        let html = "<div >some text</ div>< b>bold text< / B >";
+        let tgs = prepare_token_groups(html)?;
+        assert_eq!(tgs.len(), 2);
+        assert_eq!(serialize_token_groups(&tgs), html);
+
+        let tgs = TokenGroup::from_string(html).subdivide(20, &[])?;
+        assert_eq!(
+            serialize_token_groups(&tgs),
+            "<div >some </ div><div >text</ div>< b>bold text< / B >"
+        );
+
+        let result = prepare_token_groups("<b><i>x</b></i>");
+        assert!(matches!(
+            result,
+            Err(SplitError::UnbalancedToken(Token::CloseTag("</b>", 7)))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_plain_text() {
+        let text = TokenGroup::from_string(SHORT_HTML).to_plain_text();
+        assert_eq!(
+            text,
+            "bold italic bold italic bold strikethrough italic bold strikethrough spoiler \
+             underline italic bold bold"
+        );
+        assert!(!text.contains(['<', '>']));
+
+        let tg = TokenGroup::from_string("a<br/>b<!-- comment -->c");
+        assert_eq!(tg.to_plain_text(), "abc");
+    }
+
+    #[test]
+    fn test_normalize_self_closing() {
+        let tg = TokenGroup::from_string("<b/>text<img src='x.png' /><svg:rect/>");
+        assert_eq!(tg.to_string(), "<b/>text<img src='x.png' /><svg:rect/>");
+
+        let tg = tg.normalize_self_closing(true);
+        assert_eq!(
+            tg.to_string(),
+            "<b></b>text<img src='x.png'><svg:rect></svg:rect>"
+        );
+        assert_eq!(
+            TokenGroup::from_string("<b/>text")
+                .normalize_self_closing(true)
+                .to_string(),
+            "<b></b>text"
+        );
+        assert_eq!(
+            TokenGroup::from_string("<<br/>x<b />")
+                .normalize_self_closing(true)
+                .to_string(),
+            "<<br/>x<b></b>"
+        );
+    }
+
+    #[test]
+    fn test_subdivide_preformatted() -> TestResult {
+        let html = "<pre>fn main() {\n    let x = 1;\n    let y = 2;\n}\n</pre>";
+        let tg = TokenGroup::from_string(html);
+        let chunks = tg
+            .subdivide(40, &[])?
+            .iter()
+            .map(|tg| tg.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            vec![
+                "<pre>fn main() {\n    let x = 1;\n</pre>",
+                "<pre>    let y = 2;\n}\n</pre>",
+            ]
+        );
+
+        // without the pre, the text is split at any whitespace
+        let tg = TokenGroup::from_string("<b>fn main() {\n    let x = 1;\n    let y = 2;\n}\n</b>");
+        assert_eq!(
+            tg.subdivide(40, &[])?[0].to_string(),
+            "<b>fn main() {\n    let x = 1;\n    </b>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_subdivide_deep_nesting() -> TestResult {
+        let text = "word ".repeat(200);
+        let html = format!("{}{text}{}", "<b>".repeat(500), "</b>".repeat(500));
+        let tgs = TokenGroup::from_string(&html).subdivide(3600, &[])?;
+
+        assert_eq!(tgs.len(), 10);
+        assert_eq!(
+            tgs.iter().map(|tg| tg.to_plain_text()).collect::<String>(),
+            text
+        );
+        for tg in &tgs {
+            assert!(tg.len <= 3600);
+            assert_eq!(tg.tokens.len(), 1001);
+            assert!(tg.open_tags().is_empty());
+
+            // every chunk reopens all of the tags around its piece of the text
+            let chunk = tg.to_string();
+            crate::validate_balanced(&chunk)?;
+            assert_eq!(
+                chunk,
+                format!(
+                    "{}{}{}",
+                    "<b>".repeat(500),
+                    tg.to_plain_text(),
+                    "</b>".repeat(500)
+                )
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_tokens() {
+        let tg = TokenGroup::from_string(SHORT_HTML);
+        let text_tokens = tg.text_tokens().collect::<Vec<_>>();
+        assert_eq!(
+            text_tokens,
+            vec![
+                ("bold ", 3),
+                ("italic bold ", 11),
+                ("italic bold strikethrough ", 26),
+                ("italic bold strikethrough spoiler", 77),
+                (" ", 121),
+                ("underline italic bold", 125),
+                (" bold", 154),
+            ]
+        );
+        for (text, index) in text_tokens {
+            assert_eq!(&SHORT_HTML[index..index + text.len()], text);
+        }
+    }
+
+    #[test]
+    fn test_owned_token_group_from_str() -> TestResult {
+        let owned = SHORT_HTML.parse::<OwnedTokenGroup>()?;
+        assert_eq!(owned.to_string(), SHORT_HTML);
+
+        let tg = TokenGroup::from_string(SHORT_HTML);
+        assert_eq!(owned.as_token_group().tokens, tg.tokens);
+        assert_eq!(owned.as_token_group().len, tg.len);
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_len() {
+        let tg = TokenGroup::from_string("<b>bold</b> <i>👍</i>");
+        assert_eq!(tg.text_len(), 9);
+        assert_eq!(tg.with_length_mode(LengthMode::VisibleText).text_len(), 6);
+    }
+
+    #[test]
+    fn test_close_token_indices() -> TestResult {
+        let item = "<li><b>bold <i>italic</i></b> <a href='x'>link</a> text</li>";
+        let html = format!("<ul>{}</ul>", item.repeat(2000));
+        let tg = TokenGroup::from_string(&html);
+
+        let (map, close_indices) = tg.prepare_open_close_map()?;
+        for (index, token) in tg.tokens.iter().enumerate() {
+            if !token.is_open() {
+                continue;
+            }
+            // the linear scan used before
+            let close_token = map[token];
+            let expected = tg.tokens[index..]
+                .iter()
+                .position(|token| token == &close_token)
+                .map(|position| index + position);
+            assert_eq!(close_indices.get(&index).copied(), expected);
+        }
+
+        let tgs = tg.subdivide(200, &["a", "b"])?;
+        assert_eq!(clean(serialize_token_groups(&tgs)), clean(&html));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail() {
+        let tg = TokenGroup::from_string("<b>bold text</b> <i>italic</i> end");
+        assert_eq!(tg.tail(4, true).to_string(), " end");
+        assert_eq!(tg.tail(17, true).to_string(), "<i>italic</i> end");
+        assert_eq!(tg.tail(28, true).to_string(), " <i>italic</i> end");
+        assert_eq!(
+            tg.tail(29, true).to_string(),
+            "<b>text</b> <i>italic</i> end"
+        );
+        assert_eq!(tg.tail(2, true).to_string(), "");
+        assert_eq!(tg.tail(26, false).to_string(), "text</b> <i>italic</i> end");
+    }
+
     #[test]
     fn test_sample1() -> TestResult {
         let html = include_str!("./test_data/sample1.html");
@@ -433,4 +1994,104 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_write_to() -> TestResult {
+        let tgs = TokenGroup::from_string(LONG_HTML).subdivide(200, &[])?;
+        let mut buffer = String::new();
+        for tg in &tgs {
+            let start = buffer.len();
+            tg.write_to(&mut buffer);
+            assert_eq!(buffer[start..], tg.to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_subdivision_impossible_min_chunk_size() -> TestResult {
+        let token_group = TokenGroup::from_string(SHORT_HTML);
+        let Err(SplitError::SubdivisionImpossible { min_chunk_size, .. }) =
+            token_group.subdivide(20, &["a"])
+        else {
+            panic!("Expected SubdivisionImpossible");
+        };
+        assert_eq!(min_chunk_size, 54);
+        token_group.subdivide(min_chunk_size, &["a"])?;
+
+        let token_group = TokenGroup::from_string("<b><i>a longword</i></b>");
+        let options = SplitOptions::default()
+            .max_chunk_size(10)
+            .word_break(WordBreak::Never);
+        let Err(SplitError::SubdivisionImpossible { min_chunk_size, .. }) =
+            token_group.subdivide_with(&options)
+        else {
+            panic!("Expected SubdivisionImpossible");
+        };
+        // <b><i></i></b> and longword
+        assert_eq!(min_chunk_size, 22);
+        token_group.subdivide_with(&options.max_chunk_size(min_chunk_size))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_subdivision_impossible_open_tags() {
+        let token_group = TokenGroup::from_string("<b><i><u>some text</u></i></b>");
+        let options = SplitOptions::default()
+            .max_chunk_size(22)
+            .word_break(WordBreak::Never);
+        let Err(SplitError::SubdivisionImpossible { open_tags, .. }) =
+            token_group.subdivide_with(&options)
+        else {
+            panic!("Expected SubdivisionImpossible");
+        };
+        assert_eq!(open_tags, vec!["b", "i", "u"]);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let tg = TokenGroup::from_string(SHORT_HTML);
+        assert_eq!(tg.token_count(), 17);
+        assert_eq!(tg.tag_count(), 5);
+        assert!(tg.open_tags().is_empty());
+
+        let end = SHORT_HTML.find(" <u>").unwrap();
+        let tg = TokenGroup::from_string(&SHORT_HTML[..end]);
+        assert_eq!(tg.token_count(), 10);
+        assert_eq!(tg.tag_count(), 4);
+        assert_eq!(tg.open_tags(), vec!["b", "i"]);
+
+        let tg = TokenGroup::from_string("<p>a<br>b<img src=x/><i>c</p>");
+        assert_eq!(tg.tag_count(), 2);
+        assert_eq!(tg.open_tags(), vec!["i"]);
+
+        let tg = TokenGroup::from_string("<p>a<i>b</p><b>c</b>");
+        assert_eq!(tg.open_tags(), vec!["i"]);
+    }
+
+    #[test]
+    fn test_to_string_lossless() {
+        let tg = TokenGroup::from_string("<b></b>X");
+        assert_eq!(tg.to_string(), "X");
+        assert_eq!(tg.to_string_lossless(), "<b></b>X");
+
+        let html = "<p>a<a id=x></a><b><i></i><u></u></b>b</p><i></i>";
+        let tg = TokenGroup::from_string(html);
+        assert_eq!(tg.to_string(), "<p>ab</p>");
+        assert_eq!(tg.to_string_lossless(), html);
+
+        let mut tg = TokenGroup::new(LengthMode::Utf8).keep_empty_tags(true);
+        for token in Tokenizer::new(html) {
+            tg.push(token);
+        }
+        assert_eq!(tg.to_string(), html);
+        assert_eq!(tg.to_string_lossless(), html);
+
+        // the tags reopened and closed right away are not in the source
+        let tgs = TokenGroup::from_string("<b>aaaa bbbb</b>")
+            .subdivide(10, &[])
+            .unwrap();
+        for tg in &tgs {
+            assert_eq!(tg.to_string_lossless(), tg.to_string());
+        }
+    }
 }
@@ -1,27 +1,180 @@
 use crate::token::Token;
 
+const COMMENT_START: &str = "<!--";
+const COMMENT_END: &str = "-->";
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+#[cfg(feature = "memchr")]
+fn find_byte(bytes: &[u8], byte: u8) -> Option<usize> {
+    memchr::memchr(byte, bytes)
+}
+
+#[cfg(not(feature = "memchr"))]
+fn find_byte(bytes: &[u8], byte: u8) -> Option<usize> {
+    bytes.iter().position(|&other| other == byte)
+}
+
+/// Finds the first `>` or a quote
+#[cfg(feature = "memchr")]
+fn find_tag_end_or_quote(bytes: &[u8]) -> Option<usize> {
+    memchr::memchr3(b'>', b'"', b'\'', bytes)
+}
+
+#[cfg(not(feature = "memchr"))]
+fn find_tag_end_or_quote(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .iter()
+        .position(|byte| matches!(byte, b'>' | b'"' | b'\''))
+}
+
+#[cfg(feature = "memchr")]
+fn find_str(text: &str, needle: &str) -> Option<usize> {
+    memchr::memmem::find(text.as_bytes(), needle.as_bytes())
+}
+
+#[cfg(not(feature = "memchr"))]
+fn find_str(text: &str, needle: &str) -> Option<usize> {
+    text.find(needle)
+}
+
+/// Finds the position of the `>` closing a tag, skipping the ones inside quoted attribute values.
+/// A quote that doesn't start a value, like in `title=it's`, is just a character.
+fn find_tag_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut position = 0;
+    loop {
+        let index = position + find_tag_end_or_quote(&bytes[position..])?;
+        let quote = match bytes[index] {
+            b'>' => return Some(index),
+            quote => quote,
+        };
+        if !bytes[..index].trim_ascii_end().ends_with(b"=") {
+            position = index + 1;
+            continue;
+        }
+        position = index + 1 + find_byte(&bytes[index + 1..], quote)? + 1;
+    }
+}
+
+/// The elements whose content is raw text rather than markup
+pub(crate) const RAW_TEXT_TAGS: &[&str] = &["script", "style"];
+
+/// The name of the raw text element `tag` opens, like `script`
+pub(crate) fn raw_text_tag(tag: &str) -> Option<&'static str> {
+    let name = tag[1..].trim_start();
+    let name = &name[..name
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(name.len())];
+    RAW_TEXT_TAGS
+        .iter()
+        .find(|raw_text_tag| raw_text_tag.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Finds the close tag of the raw text element `name`, ignoring the ASCII case; the end of `text`
+/// if there's none
+fn find_raw_text_end(text: &str, name: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut position = 0;
+    while let Some(index) = find_byte(&bytes[position..], b'<') {
+        let index = position + index;
+        let rest = &bytes[index + 1..];
+        let is_close = rest.first() == Some(&b'/')
+            && rest
+                .get(1..=name.len())
+                .is_some_and(|other| other.eq_ignore_ascii_case(name.as_bytes()))
+            && rest
+                .get(name.len() + 1)
+                .is_some_and(|&byte| byte == b'>' || byte == b'/' || byte.is_ascii_whitespace());
+        if is_close {
+            return index;
+        }
+        position = index + 1;
+    }
+
+    text.len()
+}
+
+/// Splits HTML into tags, text and comments, without building a tree. Never fails: the
+/// malformed markup ends up as text. The content of `<script>` and `<style>` is a single text
+/// token, whatever it looks like, and the adjacent text is always merged into one token.
+///
+/// ```
+/// use dumb_html_splitter::token::Token;
+/// use dumb_html_splitter::tokenizer::Tokenizer;
+///
+/// let tokens = Tokenizer::new("<b>bold</b> text").collect::<Vec<_>>();
+/// assert_eq!(
+///     tokens,
+///     vec![
+///         Token::OpenTag("<b>", 0),
+///         Token::Text("bold", 3),
+///         Token::CloseTag("</b>", 7),
+///         Token::Text(" text", 11),
+///     ]
+/// );
+/// assert_eq!(tokens[0].tag_name(), "b");
+/// assert!(tokens[0].is_open() && tokens[2].is_close());
+/// assert_eq!((tokens[3].index(), tokens[3].len()), (11, 5));
+/// ```
 #[derive(Debug)]
-pub(crate) struct Tokenizer<'a> {
+pub struct Tokenizer<'a> {
+    source: &'a str,
     text: &'a str,
     index: usize,
+    raw_text_tag: Option<&'static str>,
+    /// The token read past the end of a text
+    pending: Option<Token<'a>>,
+    /// The position and the start of the first malformed markup kept as text
+    malformed: Option<(usize, &'a str)>,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub(crate) fn new(text: &'a str) -> Self {
-        Self { text, index: 0 }
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            source: text,
+            text,
+            index: 0,
+            raw_text_tag: None,
+            pending: None,
+            malformed: None,
+        }
     }
-}
 
-impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token<'a>;
+    /// The byte offset and the beginning of the first malformed markup read so far, like an
+    /// unterminated tag or comment, or `<>`. It's kept as text by the tokenizer.
+    pub fn malformed(&self) -> Option<(usize, &'a str)> {
+        self.malformed
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn mark_malformed(&mut self, markup: &'a str) {
+        const SNIPPET_LEN: usize = 32;
+
+        let mut end = markup.len().min(SNIPPET_LEN);
+        while !markup.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.malformed.get_or_insert((self.index, &markup[..end]));
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
         let text = self.text;
         if text.is_empty() {
             return None;
         }
 
-        let Some(open_pos) = text.find('<') else {
+        if let Some(name) = self.raw_text_tag.take() {
+            let end = find_raw_text_end(text, name);
+            if end != 0 {
+                let token = Token::Text(&text[..end], self.index);
+                self.index += end;
+                self.text = &text[end..];
+                return Some(token);
+            }
+        }
+
+        let Some(open_pos) = find_byte(text.as_bytes(), b'<') else {
             let token = Token::Text(text, self.index);
             self.text = "";
             return Some(token);
@@ -35,18 +188,67 @@ impl<'a> Iterator for Tokenizer<'a> {
             return Some(token);
         }
 
-        let close_pos = text[open_pos + 1..]
-            .find('>')
-            .expect("missing close bracket")
-            + open_pos
-            + 1;
+        if let Some(comment) = text.strip_prefix(COMMENT_START) {
+            // an unterminated comment swallows the rest of the input
+            let end = match find_str(comment, COMMENT_END) {
+                Some(pos) => pos + COMMENT_START.len() + COMMENT_END.len(),
+                None => {
+                    self.mark_malformed(text);
+                    text.len()
+                }
+            };
+            let token = Token::Comment(&text[..end], self.index);
+            self.index += end;
+            self.text = &text[end..];
+            return Some(token);
+        }
+
+        // the `>` inside a CDATA section doesn't end it, and an unterminated one swallows the rest
+        // of the input just like a comment
+        if let Some(cdata) = text.strip_prefix(CDATA_START) {
+            let end = match find_str(cdata, CDATA_END) {
+                Some(pos) => pos + CDATA_START.len() + CDATA_END.len(),
+                None => {
+                    self.mark_malformed(text);
+                    text.len()
+                }
+            };
+            let token = Token::Declaration(&text[..end], self.index);
+            self.index += end;
+            self.text = &text[end..];
+            return Some(token);
+        }
+
+        // A stray `<` that is never closed is most likely a part of the text (like `a < b`), or
+        // the input got truncated. Either way, there's no tag here, so we keep it as is.
+        let Some(close_pos) = find_tag_end(&text[open_pos + 1..]) else {
+            self.mark_malformed(text);
+            let token = Token::Text(text, self.index);
+            self.index += text.len();
+            self.text = "";
+            return Some(token);
+        };
+        let close_pos = close_pos + open_pos + 1;
 
         let tag = &text[..close_pos + 1];
 
+        // there's no such thing as a tag without a name, so `<>` is just text
+        let name = tag[1..tag.len() - 1].trim_start().trim_start_matches('/');
+        if name.trim().is_empty() {
+            self.mark_malformed(tag);
+            let token = Token::Text(tag, self.index);
+            self.index += close_pos + 1;
+            self.text = &text[close_pos + 1..];
+            return Some(token);
+        }
+
         let is_close = tag.chars().skip(1).find(|ch| !ch.is_whitespace()) == Some('/');
-        let token = if is_close {
+        let token = if tag.starts_with("<!") || tag.starts_with("<?") {
+            Token::Declaration(tag, self.index)
+        } else if is_close {
             Token::CloseTag(tag, self.index)
         } else {
+            self.raw_text_tag = raw_text_tag(tag);
             Token::OpenTag(tag, self.index)
         };
         self.index += close_pos + 1;
@@ -56,9 +258,35 @@ impl<'a> Iterator for Tokenizer<'a> {
     }
 }
 
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.pending.take().or_else(|| self.next_token())?;
+        let Token::Text(text, start) = token else {
+            return Some(token);
+        };
+
+        // a stray `<` ends up in a text token of its own
+        let mut end = start + text.len();
+        loop {
+            match self.next_token() {
+                Some(Token::Text(text, index)) if index == end => end += text.len(),
+                token => {
+                    self.pending = token;
+                    break;
+                }
+            }
+        }
+
+        Some(Token::Text(&self.source[start..end], start))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use testresult::TestResult;
 
     #[test]
     fn test_open_and_close_tags() {
@@ -121,11 +349,87 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_unquoted_values() {
+        let html = "<input type=text disabled value='a b'><a href=http://x.com/>it's</a>";
+        let tokens: Vec<_> = Tokenizer::new(html).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<input type=text disabled value='a b'>", 0),
+                Token::OpenTag("<a href=http://x.com/>", 38),
+                Token::Text("it's", 60),
+                Token::CloseTag("</a>", 64),
+            ]
+        );
+        assert!(tokens[1].is_open());
+
+        let tokens: Vec<_> = Tokenizer::new("<a title=it's>x</a> 'y'").collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<a title=it's>", 0),
+                Token::Text("x", 14),
+                Token::CloseTag("</a>", 15),
+                Token::Text(" 'y'", 19),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiline_tags() -> TestResult {
+        let html = "<a\n  href=\"x\"\n  title=\"y\">link</a\n>\n<\n  /\n  b\n>";
+        let tokens: Vec<_> = Tokenizer::new(html).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<a\n  href=\"x\"\n  title=\"y\">", 0),
+                Token::Text("link", 26),
+                Token::CloseTag("</a\n>", 30),
+                Token::Text("\n", 35),
+                Token::CloseTag("<\n  /\n  b\n>", 36),
+            ]
+        );
+        assert_eq!(tokens[0].tag_name(), "a");
+        assert_eq!(tokens[2].tag_name(), "a");
+        assert_eq!(tokens[4].tag_name(), "b");
+        assert!(tokens[2].closes(&tokens[0]));
+        assert_eq!(
+            tokens[0].attributes().collect::<Vec<_>>(),
+            vec![("href", Some("x")), ("title", Some("y"))]
+        );
+
+        let html = "<b\n>bold <i\r\n>one two</i\r\n></b\n>";
+        assert_eq!(
+            crate::split(html, 30, &[])?,
+            vec![
+                "<b\n>bold <i\r\n>one </i\r\n></b\n>",
+                "<b\n><i\r\n>two</i\r\n></b\n>"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut tokenizer = Tokenizer::new("<b>a</b> <i");
+        assert_eq!(tokenizer.by_ref().count(), 4);
+        assert_eq!(tokenizer.malformed(), Some((9, "<i")));
+
+        let mut tokenizer = Tokenizer::new("a <> b <!-- comment");
+        tokenizer.by_ref().for_each(drop);
+        assert_eq!(tokenizer.malformed(), Some((2, "<>")));
+
+        let mut tokenizer = Tokenizer::new("<b>a</b><!-- comment -->");
+        tokenizer.by_ref().for_each(drop);
+        assert_eq!(tokenizer.malformed(), None);
+    }
+
     #[test]
     fn test_self_closing_tag() {
         let tokenizer = Tokenizer::new("<img src='image.png'/>");
         let tokens: Vec<_> = tokenizer.collect();
-        // TODO: introduce OpenClose support for the case + CDATA
+        // there is no token of its own for a self-closing tag, see `Token::is_self_closing`
         assert_eq!(tokens, vec![Token::OpenTag("<img src='image.png'/>", 0)]);
     }
 
@@ -142,4 +446,205 @@ mod tests {
         let tokens: Vec<_> = tokenizer.collect();
         assert_eq!(tokens, vec![Token::OpenTag("<tag>", 0)]);
     }
+
+    #[test]
+    fn test_comment_with_angle_brackets() {
+        let tokenizer = Tokenizer::new("<b>x</b><!-- a > b <i> --> after");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<b>", 0),
+                Token::Text("x", 3),
+                Token::CloseTag("</b>", 4),
+                Token::Comment("<!-- a > b <i> -->", 8),
+                Token::Text(" after", 26),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let tokenizer = Tokenizer::new("text<!-- never <b>closed");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("text", 0),
+                Token::Comment("<!-- never <b>closed", 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_declarations() {
+        let tokenizer = Tokenizer::new(r#"<?xml version="1.0"?><!DOCTYPE html><p>text</p>"#);
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Declaration(r#"<?xml version="1.0"?>"#, 0),
+                Token::Declaration("<!DOCTYPE html>", 21),
+                Token::OpenTag("<p>", 36),
+                Token::Text("text", 39),
+                Token::CloseTag("</p>", 43),
+            ]
+        );
+        assert!(!tokens[1].is_open());
+    }
+
+    #[test]
+    fn test_cdata() -> TestResult {
+        let html = "<p><![CDATA[ a > b ]] ]>]]></p><![CDATA[x]]>";
+        let tokens: Vec<_> = Tokenizer::new(html).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<p>", 0),
+                Token::Declaration("<![CDATA[ a > b ]] ]>]]>", 3),
+                Token::CloseTag("</p>", 27),
+                Token::Declaration("<![CDATA[x]]>", 31),
+            ]
+        );
+        assert_eq!(tokens.iter().map(Token::as_text).collect::<String>(), html);
+        assert_eq!(
+            crate::split(html, 31, &[])?,
+            vec!["<p><![CDATA[ a > b ]] ]>]]></p>", "<![CDATA[x]]>"]
+        );
+
+        let html = "a<![CDATA[ b > c";
+        let mut tokenizer = Tokenizer::new(html);
+        assert_eq!(
+            tokenizer.by_ref().collect::<Vec<_>>(),
+            vec![
+                Token::Text("a", 0),
+                Token::Declaration("<![CDATA[ b > c", 1)
+            ]
+        );
+        assert_eq!(tokenizer.malformed(), Some((1, "<![CDATA[ b > c")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stray_open_bracket() {
+        let tokenizer = Tokenizer::new("a < b");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(tokens, vec![Token::Text("a < b", 0)]);
+    }
+
+    #[test]
+    fn test_empty_tag_is_text() {
+        let tokenizer = Tokenizer::new("a<>b</ ><i>c</i>");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("a<>b</ >", 0),
+                Token::OpenTag("<i>", 8),
+                Token::Text("c", 11),
+                Token::CloseTag("</i>", 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_tag() {
+        let tokenizer = Tokenizer::new("<b>x</b><unterminated");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<b>", 0),
+                Token::Text("x", 3),
+                Token::CloseTag("</b>", 4),
+                Token::Text("<unterminated", 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_bracket_inside_double_quotes() {
+        let tokenizer = Tokenizer::new(r#"<a title="a > b">text</a>"#);
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag(r#"<a title="a > b">"#, 0),
+                Token::Text("text", 17),
+                Token::CloseTag("</a>", 21),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_bracket_inside_single_quotes() {
+        let tokenizer = Tokenizer::new("<img alt='1 > 0'/>after");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<img alt='1 > 0'/>", 0),
+                Token::Text("after", 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_content_is_text() {
+        let html = "<script>if (a<b){}</script><b>x</b>";
+        let tokens: Vec<_> = Tokenizer::new(html).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<script>", 0),
+                Token::Text("if (a<b){}", 8),
+                Token::CloseTag("</script>", 18),
+                Token::OpenTag("<b>", 27),
+                Token::Text("x", 30),
+                Token::CloseTag("</b>", 31),
+            ]
+        );
+        assert_eq!(tokens.iter().map(Token::as_text).collect::<String>(), html);
+    }
+
+    #[test]
+    fn test_style_content_is_text() {
+        let tokenizer = Tokenizer::new("<STYLE type='text/css'>a > b { }</scripts></Style >");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<STYLE type='text/css'>", 0),
+                Token::Text("a > b { }</scripts>", 23),
+                Token::CloseTag("</Style >", 42),
+            ]
+        );
+
+        let tokenizer = Tokenizer::new("<script></script><script>x < y");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<script>", 0),
+                Token::CloseTag("</script>", 8),
+                Token::OpenTag("<script>", 17),
+                Token::Text("x < y", 25),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adjacent_text_is_merged() {
+        let tokenizer = Tokenizer::new("<b>x</b> 1 < 2");
+        let tokens: Vec<_> = tokenizer.collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpenTag("<b>", 0),
+                Token::Text("x", 3),
+                Token::CloseTag("</b>", 4),
+                Token::Text(" 1 < 2", 8),
+            ]
+        );
+    }
 }